@@ -1,7 +1,9 @@
-{ config, lib, ... }:
+{ config, lib, pkgs, ... }:
 
 let
   cfg = config.services.niritiling;
+  settingsFormat = pkgs.formats.json { };
+  configFile = settingsFormat.generate "niritiling.json" cfg.settings;
 in
 {
   options.services.niritiling = {
@@ -12,6 +14,15 @@ in
       default = "graphical-session.target";
       description = "The systemd target to bind the niritiling service to.";
     };
+
+    settings = lib.mkOption {
+      inherit (settingsFormat) type;
+      default = { };
+      example = {
+        coalesce_window_ms = 50;
+      };
+      description = "Configuration for niritiling, written to a JSON file passed via `--config`.";
+    };
   };
 
   config = lib.mkIf cfg.enable {
@@ -22,9 +33,10 @@ in
       wantedBy = [ cfg.systemdTarget ];
 
       serviceConfig = {
-        ExecStart = "${cfg.package}/bin/niritiling";
+        ExecStart = "${cfg.package}/bin/niritiling --config ${configFile}";
         Restart = "on-failure";
         RestartSec = 2;
+        RuntimeDirectory = "niritiling";
 
         CapabilityBoundingSet = "";
         IPAddressDeny = "any";
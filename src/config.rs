@@ -0,0 +1,359 @@
+use crate::niri::SizeChange;
+use crate::niri_config::NiriLayout;
+use anyhow::{Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// User configuration, read from a JSON file.
+///
+/// Every field is optional; missing fields fall back to [`Config::default`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// How long to keep gathering events after the first one of a burst before evaluating the
+    /// affected workspaces, in milliseconds.
+    pub coalesce_window_ms: u64,
+    /// How long to wait for niri to answer a single request before reconnecting, in
+    /// milliseconds.
+    pub ipc_timeout_ms: u64,
+    /// How often to poll niri for output changes (hotplug, scale, mode) while idle, in
+    /// milliseconds. `0` disables polling.
+    pub output_poll_interval_ms: u64,
+    /// Columns with more tiles than this are switched to tabbed display, and back once they have
+    /// shrunk again. `0` disables it.
+    pub auto_tab_threshold: usize,
+    /// How columns are sized once a workspace has more than one. A single column is always
+    /// maximized.
+    pub mode: Mode,
+    /// Column widths in accordion mode.
+    pub accordion: AccordionConfig,
+    /// Most columns a workspace may have; windows that would open another column are stacked
+    /// into an existing one instead. `0` means no limit.
+    pub max_columns: usize,
+    /// Which column windows over the `max_columns` limit are stacked into.
+    pub overflow_into: OverflowTarget,
+    /// Settings for individual named workspaces, overriding the ones above.
+    pub workspaces: HashMap<String, WorkspaceConfig>,
+    /// Whether to give all tiles of a column the same height whenever a tile joins or leaves it.
+    pub equalize_tile_heights: bool,
+    /// Whether workspaces on portrait outputs (taller than wide) stack new windows into a single
+    /// full-width column instead of opening side-by-side columns.
+    pub stack_on_portrait: bool,
+    /// What to do with the viewport after columns were un-maximized or a column was closed.
+    pub viewport: ViewportPolicy,
+    /// niri's `config.kdl` to inherit layout settings from, instead of niri's default location.
+    pub niri_config: Option<PathBuf>,
+    /// Geometry of niri's layout, used to tell maximized columns apart. Settings left out here
+    /// are taken from niri's config.
+    pub layout: LayoutConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            coalesce_window_ms: 30,
+            ipc_timeout_ms: 2000,
+            output_poll_interval_ms: 2000,
+            auto_tab_threshold: 0,
+            mode: Mode::default(),
+            accordion: AccordionConfig::default(),
+            max_columns: 0,
+            overflow_into: OverflowTarget::default(),
+            workspaces: HashMap::new(),
+            equalize_tile_heights: false,
+            stack_on_portrait: false,
+            viewport: ViewportPolicy::default(),
+            niri_config: None,
+            layout: LayoutConfig::default(),
+        }
+    }
+}
+
+/// Settings for a single named workspace. Unset values fall back to the global ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    pub mode: Option<Mode>,
+    pub max_columns: Option<usize>,
+}
+
+/// How the columns of a workspace with more than one column are sized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Columns keep the width they had before the workspace was down to a single column.
+    #[default]
+    Maximize,
+    /// The column with the focused window is wide and all others are narrow, following focus.
+    Accordion,
+    /// Every column is maximized, so scrolling flips between full-width columns.
+    Monocle,
+    /// Spiral-like tiling: new windows alternately open a column half as wide as the previous
+    /// one or split the last column vertically.
+    Dwindle,
+}
+
+/// Column widths in [`Mode::Accordion`], as proportions of the output width.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccordionConfig {
+    pub focused: f64,
+    pub others: f64,
+}
+
+impl Default for AccordionConfig {
+    fn default() -> Self {
+        Self {
+            focused: 0.75,
+            others: 0.25,
+        }
+    }
+}
+
+/// The column windows over the column limit are stacked into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowTarget {
+    /// The column the window opened next to, which is the focused one.
+    #[default]
+    Focused,
+    /// The rightmost column.
+    Last,
+}
+
+/// How niritiling moves the viewport after the columns of a workspace changed under it.
+///
+/// Focus always ends up on the window that had it before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewportPolicy {
+    /// Leave the viewport where niri put it.
+    Keep,
+    /// Scroll one column to the left, so the column left of the focused one comes into view.
+    #[default]
+    NudgeLeft,
+    /// Center the focused column.
+    CenterFocused,
+    /// Scroll towards the first column, as far as the focused column stays visible.
+    SnapFirst,
+    /// Center the columns that are fully visible, spreading the free space around them.
+    ///
+    /// This doesn't scroll further columns into view: niri has no action for showing as many
+    /// columns as fit, and only centers the ones already visible.
+    Fit,
+}
+
+/// niri's default `gaps`, used when neither config sets them.
+const DEFAULT_GAPS: f64 = 16.0;
+
+/// Mirrors the parts of niri's `layout` config that determine column widths.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    /// Gaps between and around columns, in logical pixels.
+    pub gaps: Option<f64>,
+    /// Space reserved at the output edges, in logical pixels.
+    pub struts: Option<Struts>,
+    /// Width a column goes back to when it stops being the only one and its width from before
+    /// it was maximized is unknown, e.g. because it opened as the only column.
+    pub default_column_width: Option<ColumnWidth>,
+    /// A column at least this wide, relative to the width a maximized column gets, is
+    /// considered maximized.
+    pub maximized_ratio: f64,
+    /// A column narrower than this, relative to the width a maximized column gets, is
+    /// considered not maximized. Between the two ratios the previous decision for the window
+    /// stands.
+    pub unmaximized_ratio: f64,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            gaps: None,
+            struts: None,
+            default_column_width: None,
+            maximized_ratio: 0.95,
+            unmaximized_ratio: 0.9,
+        }
+    }
+}
+
+/// niri's `struts`. Only `left` and `right` affect column widths; `top` and `bottom` are
+/// accepted so the section can be copied from the niri config as is.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Struts {
+    pub left: f64,
+    pub right: f64,
+    pub top: f64,
+    pub bottom: f64,
+}
+
+/// A column width as written in niri's config: `{"proportion": 0.5}` or `{"fixed": 1200}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum ColumnWidth {
+    /// Fraction of the output width.
+    Proportion(f64),
+    /// Logical pixels.
+    Fixed(f64),
+}
+
+impl ColumnWidth {
+    /// The resize that sets a window to this width.
+    pub fn size_change(self) -> SizeChange {
+        match self {
+            // niri's IPC takes proportions in percent.
+            Self::Proportion(p) => SizeChange::SetProportion(p * 100.0),
+            Self::Fixed(px) => SizeChange::SetFixed(px.round() as i32),
+        }
+    }
+}
+
+impl LayoutConfig {
+    /// Fills in the settings not configured here from niri's own config.
+    pub fn inherit(&mut self, niri: &NiriLayout) {
+        self.gaps = self.gaps.or(niri.gaps);
+        if self.struts.is_none() {
+            self.struts = niri.struts.clone();
+        }
+        self.default_column_width = self.default_column_width.or(niri.default_column_width);
+    }
+
+    /// Width niri gives a maximized column on an output of the given logical width.
+    pub fn maximized_width(&self, output_width: f64) -> f64 {
+        let gaps = self.gaps.unwrap_or(DEFAULT_GAPS);
+        let (left, right) = self
+            .struts
+            .as_ref()
+            .map_or((0.0, 0.0), |s| (s.left, s.right));
+        output_width - left - right - 2.0 * gaps
+    }
+
+    /// Classifies a column by its width relative to [`Self::maximized_width`], keeping
+    /// `previously` when the ratio falls between the two thresholds.
+    pub fn is_maximized_ratio(&self, ratio: f64, previously: Option<bool>) -> bool {
+        if ratio >= self.maximized_ratio {
+            true
+        } else if ratio < self.unmaximized_ratio {
+            false
+        } else {
+            previously.unwrap_or(false)
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or from the default location if `path` is `None`.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let default = config_home().map(|base| base.join("niritiling").join("config.json"));
+        let Some((path, contents)) = read_optional(path, default)? else {
+            return Ok(Self::default());
+        };
+        let config: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("parsing config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+        info!("loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        // A zero socket timeout is rejected by std, so niri could never be connected to.
+        if self.ipc_timeout_ms == 0 {
+            anyhow::bail!("ipc_timeout_ms must be greater than 0");
+        }
+        for (key, value) in [
+            ("accordion.focused", self.accordion.focused),
+            ("accordion.others", self.accordion.others),
+        ] {
+            if !(value > 0.0 && value <= 1.0) {
+                anyhow::bail!("{} ({}) must be a proportion in (0, 1]", key, value);
+            }
+        }
+        let layout = &self.layout;
+        if layout.unmaximized_ratio > layout.maximized_ratio {
+            anyhow::bail!(
+                "layout.unmaximized_ratio ({}) must not exceed layout.maximized_ratio ({})",
+                layout.unmaximized_ratio,
+                layout.maximized_ratio
+            );
+        }
+        Ok(())
+    }
+
+    /// Settings of the workspace called `name`, if it has any.
+    fn workspace(&self, name: Option<&str>) -> Option<&WorkspaceConfig> {
+        name.and_then(|name| self.workspaces.get(name))
+    }
+
+    /// Mode of the workspace called `workspace`.
+    pub fn mode_of(&self, workspace: Option<&str>) -> Mode {
+        self.workspace(workspace)
+            .and_then(|ws| ws.mode)
+            .unwrap_or(self.mode)
+    }
+
+    /// Whether any workspace has a mode that depends on which window is focused.
+    pub fn follows_focus(&self) -> bool {
+        self.mode == Mode::Accordion
+            || self
+                .workspaces
+                .values()
+                .any(|ws| ws.mode == Some(Mode::Accordion))
+    }
+
+    /// Column limit of the workspace called `name`, or `None` if it has none.
+    pub fn max_columns_of(&self, workspace: Option<&str>) -> Option<usize> {
+        let max = self
+            .workspace(workspace)
+            .and_then(|ws| ws.max_columns)
+            .unwrap_or(self.max_columns);
+        (max > 0).then_some(max)
+    }
+
+    pub fn coalesce_window(&self) -> Duration {
+        Duration::from_millis(self.coalesce_window_ms)
+    }
+
+    pub fn ipc_timeout(&self) -> Duration {
+        Duration::from_millis(self.ipc_timeout_ms)
+    }
+
+    pub fn output_poll_interval(&self) -> Option<Duration> {
+        (self.output_poll_interval_ms > 0)
+            .then(|| Duration::from_millis(self.output_poll_interval_ms))
+    }
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`.
+pub fn config_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Reads the file at `path`, or at `default` if `path` is `None`, returning the file that was
+/// read along with its contents.
+///
+/// A missing file at the default location is not an error; an explicitly given path must exist.
+pub fn read_optional(
+    path: Option<&Path>,
+    default: Option<PathBuf>,
+) -> Result<Option<(PathBuf, String)>> {
+    let path = match path {
+        Some(p) => p.to_path_buf(),
+        None => match default {
+            Some(p) if p.exists() => p,
+            _ => return Ok(None),
+        },
+    };
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(Some((path, contents)))
+}
@@ -1,14 +1,27 @@
-use anyhow::{Context, Result};
-use log::{error, warn};
-use niri_ipc::socket::Socket;
-use niri_ipc::{Action, Request, Response, Window};
-use std::collections::HashMap;
+use crate::error::{NiriError, NiriResult};
+use crate::niri::{
+    Action, Event, Reply, Request, Response, SOCKET_PATH_ENV, SUPPORTED_NIRI_VERSION, Window,
+    newer_events,
+};
+use crate::status::Stats;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone, Default)]
 pub struct NiriState {
     pub windows: Vec<Window>,
     pub output_widths: HashMap<String, f64>,
+    /// Logical heights of the outputs, for telling portrait outputs apart.
+    pub output_heights: HashMap<String, f64>,
     pub ws_outputs: HashMap<u64, String>,
+    /// Names of the named workspaces.
+    pub ws_names: HashMap<u64, String>,
+    pub focused_workspace: Option<u64>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -16,114 +29,421 @@ pub struct WindowPosition {
     pub workspace_id: u64,
     pub column: Option<usize>,
     pub tile: Option<usize>,
+    /// The window is fullscreen; `column` and `tile` are where it sat before, if known.
+    ///
+    /// Windowed (fake) fullscreen keeps the window in the layout and is not flagged here.
+    pub fullscreen: bool,
+}
+
+impl WindowPosition {
+    /// Builds the tracked position of a tiled window from niri's layout information.
+    ///
+    /// niri reports no scrolling-layout position for fullscreen windows. They still own their
+    /// column, so the column and tile last seen on the same workspace are carried over.
+    pub fn from_layout(
+        workspace_id: u64,
+        pos_in_scrolling_layout: Option<(usize, usize)>,
+        previous: Option<&WindowPosition>,
+    ) -> Self {
+        match pos_in_scrolling_layout {
+            Some((column, tile)) => Self {
+                workspace_id,
+                column: Some(column),
+                tile: Some(tile),
+                fullscreen: false,
+            },
+            None => {
+                let previous = previous.filter(|p| p.workspace_id == workspace_id);
+                Self {
+                    workspace_id,
+                    column: previous.and_then(|p| p.column),
+                    tile: previous.and_then(|p| p.tile),
+                    fullscreen: true,
+                }
+            }
+        }
+    }
 }
 
 pub trait NiriConnection: Send {
-    fn send_action(&mut self, action: Action) -> Result<()>;
-    fn query_focused_window(&mut self) -> Result<Option<u64>>;
-    fn query_full_state(&mut self) -> Result<NiriState>;
+    fn send_action(&mut self, action: Action) -> NiriResult<()>;
+    fn query_focused_window(&mut self) -> NiriResult<Option<u64>>;
+    fn query_output_widths(&mut self) -> NiriResult<HashMap<String, f64>>;
+    fn query_full_state(&mut self) -> NiriResult<NiriState>;
 }
 
+/// Request/reply connection to niri.
+///
+/// Unlike [`niri_ipc::socket::Socket`] this keeps hold of the stream so every request can be
+/// bounded by a timeout instead of blocking forever when niri stalls.
+///
+/// A reply that arrives after its request timed out would be read as the answer to the next one,
+/// so once a request timed out every later one fails with [`NiriError::Timeout`] as well.
 pub struct SocketConnection {
-    socket: Socket,
+    stream: BufReader<UnixStream>,
+    stats: Arc<Stats>,
+    timed_out: bool,
 }
 
 impl SocketConnection {
-    pub fn new() -> Result<Self> {
-        let socket = Socket::connect().context("connecting to niri via socket")?;
-        Ok(Self { socket })
+    pub fn connect(path: &Path, timeout: Duration, stats: Arc<Stats>) -> NiriResult<Self> {
+        let context = "connecting to niri";
+        let stream = UnixStream::connect(path).map_err(|e| io_error(context, e))?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| io_error(context, e))?;
+        stream
+            .set_write_timeout(Some(timeout))
+            .map_err(|e| io_error(context, e))?;
+        Ok(Self {
+            stream: BufReader::new(stream),
+            stats,
+            timed_out: false,
+        })
     }
-}
 
-impl NiriConnection for SocketConnection {
-    fn send_action(&mut self, action: Action) -> Result<()> {
-        let reply = self
-            .socket
-            .send(Request::Action(action.clone()))
-            .context("sending action to niri")?;
-        match reply {
-            Ok(Response::Handled) => Ok(()),
-            Ok(other) => {
-                warn!(
-                    "unexpected response from niri for action {:?}: {:?}",
-                    action, other
-                );
-                Ok(())
-            }
-            Err(msg) => {
-                error!("niri returned error for action {:?}: {}", action, msg);
-                Ok(())
-            }
+    fn request(&mut self, request: Request, context: &'static str) -> NiriResult<Reply> {
+        if self.timed_out {
+            return Err(NiriError::Timeout { context });
         }
+        let line = write_request(&mut self.stream, &request, context)
+            .and_then(|()| read_line(&mut self.stream, context))
+            .inspect_err(|e| {
+                if matches!(e, NiriError::Timeout { .. }) {
+                    self.timed_out = true;
+                    self.stats.record_ipc_timeout();
+                }
+            })?;
+        // The whole line was consumed, so a reply we can't decode leaves the stream usable.
+        serde_json::from_str(&line).map_err(|e| NiriError::UnexpectedResponse {
+            request: context,
+            response: format!("{} ({})", line.trim_end(), e),
+        })
     }
 
-    fn query_focused_window(&mut self) -> Result<Option<u64>> {
-        let reply = self
-            .socket
-            .send(Request::FocusedWindow)
-            .context("querying focused window")?;
-        match reply {
-            Ok(Response::FocusedWindow(Some(w))) => Ok(Some(w.id)),
-            Ok(Response::FocusedWindow(None)) => Ok(None),
-            _ => {
-                warn!("unexpected response when querying focused window");
-                Ok(None)
+    /// Asks niri for its version and warns if it differs from the one we were built against.
+    ///
+    /// A mismatch is not fatal: events we can't decode are skipped by [`EventStream`]. Returns
+    /// the running release as `(year, month)`, if niri's version string could be parsed.
+    pub fn check_version(&mut self) -> NiriResult<Option<(u32, u32)>> {
+        let version = match self.request(Request::Version, "querying version")? {
+            Ok(Response::Version(version)) => version,
+            other => return Err(unexpected("version", other)),
+        };
+
+        let (major, minor) = SUPPORTED_NIRI_VERSION;
+        let running = parse_niri_version(&version);
+        match running {
+            Some(running) if running == SUPPORTED_NIRI_VERSION => {
+                info!("connected to niri {}", version);
             }
+            Some(_) => warn!(
+                "niri {} is running, but niritiling was built for niri {}.{:02}; \
+                 events that can't be understood will be ignored",
+                version, major, minor
+            ),
+            None => warn!(
+                "could not parse niri version {:?}, niritiling was built for niri {}.{:02}",
+                version, major, minor
+            ),
         }
+        Ok(running)
     }
 
-    fn query_full_state(&mut self) -> Result<NiriState> {
-        let windows = match self
-            .socket
-            .send(Request::Windows)
-            .context("querying windows")?
-        {
-            Ok(Response::Windows(w)) => w,
-            _ => anyhow::bail!("failed to query windows"),
-        };
-
-        let output_widths = match self
-            .socket
-            .send(Request::Outputs)
-            .context("querying outputs")?
-        {
+    /// Logical `(width, height)` of every enabled output.
+    fn query_output_sizes(&mut self) -> NiriResult<HashMap<String, (f64, f64)>> {
+        match self.request(Request::Outputs, "querying outputs")? {
             Ok(Response::Outputs(outputs)) => {
-                let mut widths = HashMap::new();
+                let mut sizes = HashMap::new();
                 for (name, out) in outputs {
                     if let Some(logical) = out.logical {
                         if logical.width > 0 {
-                            widths.insert(name, logical.width as f64);
+                            sizes.insert(name, (logical.width as f64, logical.height as f64));
                         } else {
                             warn!("output {} has non-positive width: {}", name, logical.width);
                         }
                     }
                 }
-                widths
+                Ok(sizes)
             }
-            _ => anyhow::bail!("failed to query outputs"),
-        };
+            other => Err(unexpected("outputs", other)),
+        }
+    }
+}
 
-        let ws_outputs = match self
-            .socket
-            .send(Request::Workspaces)
-            .context("querying workspaces")?
-        {
-            Ok(Response::Workspaces(workspaces)) => {
-                let mut mapping = HashMap::new();
-                for ws in workspaces {
-                    if let Some(output) = ws.output {
-                        mapping.insert(ws.id, output);
+/// Parses the leading `YY.MM` of a niri version string such as `25.08 (8ed0da4)`.
+pub fn parse_niri_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version
+        .trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .take(2)
+        .map(|part| part.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??))
+}
+
+/// The niri event stream.
+///
+/// Events that fail to decode, e.g. variants added in a newer niri, are logged and skipped
+/// rather than tearing down the connection.
+pub struct EventStream {
+    stream: BufReader<UnixStream>,
+    unknown_events: HashSet<String>,
+    /// Events of the running niri release that are expected not to decode.
+    newer_events: Vec<&'static str>,
+}
+
+impl EventStream {
+    pub fn connect(path: &Path) -> NiriResult<Self> {
+        let stream = UnixStream::connect(path)
+            .map_err(|e| io_error("connecting to niri event stream", e))?;
+        Self::start(stream)
+    }
+
+    /// Requests the event stream on an already connected socket.
+    pub fn start(stream: UnixStream) -> NiriResult<Self> {
+        let context = "requesting event stream";
+        let mut stream = BufReader::new(stream);
+        write_request(&mut stream, &Request::EventStream, context)?;
+        let line = read_line(&mut stream, context)?;
+        match serde_json::from_str::<Reply>(&line) {
+            Ok(Ok(Response::Handled)) => {}
+            Ok(other) => return Err(unexpected("event stream", other)),
+            Err(e) => {
+                return Err(NiriError::UnexpectedResponse {
+                    request: context,
+                    response: format!("{} ({})", line.trim_end(), e),
+                });
+            }
+        }
+        let _ = stream.get_mut().shutdown(std::net::Shutdown::Write);
+
+        Ok(Self {
+            stream,
+            unknown_events: HashSet::new(),
+            newer_events: Vec::new(),
+        })
+    }
+
+    /// Skips the events `running` added over the types we were built against without a warning.
+    pub fn set_niri_version(&mut self, running: (u32, u32)) {
+        self.newer_events = newer_events(running);
+    }
+
+    /// Blocks until the next event that can be decoded arrives.
+    pub fn next_event(&mut self) -> NiriResult<Event> {
+        loop {
+            let line = read_line(&mut self.stream, "reading event stream")?;
+            match serde_json::from_str::<Event>(&line) {
+                Ok(event) => return Ok(event),
+                Err(e) => {
+                    let name = serde_json::from_str::<serde_json::Value>(&line)
+                        .ok()
+                        .and_then(|v| v.as_object()?.keys().next().cloned())
+                        .unwrap_or_else(|| "<malformed>".to_string());
+                    if !self.newer_events.contains(&name.as_str())
+                        && self.unknown_events.insert(name.clone())
+                    {
+                        warn!(
+                            "ignoring niri event {} that could not be decoded: {}",
+                            name, e
+                        );
+                    } else {
+                        debug!("ignoring niri event {}", name);
                     }
                 }
-                mapping
             }
-            _ => anyhow::bail!("failed to query workspaces"),
+        }
+    }
+}
+
+fn write_request(
+    stream: &mut BufReader<UnixStream>,
+    request: &Request,
+    context: &'static str,
+) -> NiriResult<()> {
+    let mut buf = serde_json::to_string(request).expect("requests always serialize");
+    buf.push('\n');
+    stream
+        .get_mut()
+        .write_all(buf.as_bytes())
+        .map_err(|e| io_error(context, e))
+}
+
+fn read_line(stream: &mut BufReader<UnixStream>, context: &'static str) -> NiriResult<String> {
+    let mut buf = String::new();
+    let read = stream
+        .read_line(&mut buf)
+        .map_err(|e| io_error(context, e))?;
+    if read == 0 {
+        return Err(io_error(context, std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(buf)
+}
+
+/// Maps an I/O failure to [`NiriError::Timeout`] if the socket timeout elapsed, or to
+/// [`NiriError::Transport`] otherwise.
+fn io_error(context: &'static str, source: std::io::Error) -> NiriError {
+    match source.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+            NiriError::Timeout { context }
+        }
+        _ => NiriError::transport(context, source),
+    }
+}
+
+/// Finds a live niri IPC socket.
+///
+/// `$NIRI_SOCKET` is re-read on every call and preferred. If it is unset or points at a socket
+/// nobody listens on anymore (e.g. niri restarted within the same user session), the newest
+/// `niri.*.sock` in `$XDG_RUNTIME_DIR` that accepts a connection is used instead.
+pub fn discover_socket() -> NiriResult<PathBuf> {
+    let env_path = std::env::var_os(SOCKET_PATH_ENV).map(PathBuf::from);
+    if let Some(path) = &env_path {
+        if UnixStream::connect(path).is_ok() {
+            return Ok(path.clone());
+        }
+        debug!(
+            "${} points at dead socket {}",
+            SOCKET_PATH_ENV,
+            path.display()
+        );
+    }
+
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        for candidate in socket_candidates(Path::new(&runtime_dir)) {
+            if Some(&candidate) != env_path.as_ref() && UnixStream::connect(&candidate).is_ok() {
+                info!("discovered niri socket at {}", candidate.display());
+                return Ok(candidate);
+            }
+        }
+    }
+
+    Err(NiriError::transport(
+        "locating niri socket",
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "${} is unset or stale and no live niri.*.sock was found in $XDG_RUNTIME_DIR",
+                SOCKET_PATH_ENV
+            ),
+        ),
+    ))
+}
+
+/// Lists `niri.*.sock` entries in `dir`, most recently modified first.
+pub fn socket_candidates(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with("niri.") && name.ends_with(".sock"))
+        })
+        .map(|entry| {
+            let modified = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, entry.path())
+        })
+        .collect();
+
+    candidates.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    candidates.into_iter().map(|(_, path)| path).collect()
+}
+
+fn unexpected(request: &'static str, reply: Reply) -> NiriError {
+    NiriError::UnexpectedResponse {
+        request,
+        response: format!("{:?}", reply),
+    }
+}
+
+impl NiriConnection for SocketConnection {
+    fn send_action(&mut self, action: Action) -> NiriResult<()> {
+        match self.request(Request::Action(action.clone()), "sending action")? {
+            Ok(Response::Handled) => Ok(()),
+            Err(message) => Err(NiriError::Rejected {
+                action: Box::new(action),
+                message,
+            }),
+            other => Err(unexpected("action", other)),
+        }
+    }
+
+    fn query_focused_window(&mut self) -> NiriResult<Option<u64>> {
+        match self.request(Request::FocusedWindow, "querying focused window")? {
+            Ok(Response::FocusedWindow(w)) => Ok(w.map(|w| w.id)),
+            other => Err(unexpected("focused window", other)),
+        }
+    }
+
+    fn query_output_widths(&mut self) -> NiriResult<HashMap<String, f64>> {
+        let sizes = self.query_output_sizes()?;
+        Ok(sizes.into_iter().map(|(name, (w, _))| (name, w)).collect())
+    }
+
+    fn query_full_state(&mut self) -> NiriResult<NiriState> {
+        let windows = match self.request(Request::Windows, "querying windows")? {
+            Ok(Response::Windows(w)) => w,
+            other => return Err(unexpected("windows", other)),
         };
 
+        let output_sizes = self.query_output_sizes()?;
+        let output_widths = output_sizes
+            .iter()
+            .map(|(n, &(w, _))| (n.clone(), w))
+            .collect();
+        let output_heights = output_sizes.into_iter().map(|(n, (_, h))| (n, h)).collect();
+
+        let mut focused_workspace = None;
+        let (ws_outputs, ws_names, ws_ids) =
+            match self.request(Request::Workspaces, "querying workspaces")? {
+                Ok(Response::Workspaces(workspaces)) => {
+                    let mut mapping = HashMap::new();
+                    let mut names = HashMap::new();
+                    let mut ids = HashSet::new();
+                    for ws in workspaces {
+                        ids.insert(ws.id);
+                        if ws.is_focused {
+                            focused_workspace = Some(ws.id);
+                        }
+                        if let Some(output) = ws.output {
+                            mapping.insert(ws.id, output);
+                        }
+                        if let Some(name) = ws.name {
+                            names.insert(ws.id, name);
+                        }
+                    }
+                    (mapping, names, ids)
+                }
+                other => return Err(unexpected("workspaces", other)),
+            };
+
+        // Requests are answered one at a time, so a window can show up on a workspace that was
+        // created after we listed them.
+        if let Some(w) = windows
+            .iter()
+            .find(|w| w.workspace_id.is_some_and(|id| !ws_ids.contains(&id)))
+        {
+            return Err(NiriError::InconsistentState(format!(
+                "window {} is on unknown workspace {:?}",
+                w.id, w.workspace_id
+            )));
+        }
+
         Ok(NiriState {
             windows,
             output_widths,
+            output_heights,
             ws_outputs,
+            ws_names,
+            focused_workspace,
         })
     }
 }
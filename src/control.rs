@@ -0,0 +1,139 @@
+use crate::status::Stats;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Reply to a control command: a JSON payload on success, a message on failure.
+pub type ControlReply = std::result::Result<serde_json::Value, String>;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A command only the event loop can carry out, since it owns the niri connection.
+pub struct LoopCommand {
+    pub name: String,
+    reply: Sender<ControlReply>,
+}
+
+impl LoopCommand {
+    pub fn reply(self, reply: ControlReply) {
+        // The client may have given up waiting already.
+        let _ = self.reply.send(reply);
+    }
+}
+
+/// Channel into the event loop of the current niri connection, `None` before the first one.
+///
+/// Each connection has its own channel, so the event loop swaps in a new sender on connect.
+pub type LoopSender<T> = Arc<Mutex<Option<Sender<T>>>>;
+
+/// Path of the control socket, `$XDG_RUNTIME_DIR/niritiling/control.sock`.
+pub fn socket_path() -> Result<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").context("$XDG_RUNTIME_DIR is not set")?;
+    Ok(PathBuf::from(runtime_dir)
+        .join("niritiling")
+        .join("control.sock"))
+}
+
+/// Starts answering control commands (e.g. from `niritiling status`) on a background thread.
+///
+/// Commands that need niri are passed on to the event loop through `event_loop`.
+pub fn spawn_server<T>(stats: Arc<Stats>, event_loop: LoopSender<T>) -> Result<()>
+where
+    T: From<LoopCommand> + Send + 'static,
+{
+    let path = socket_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("creating control socket directory {}", dir.display()))?;
+    }
+
+    if UnixStream::connect(&path).is_ok() {
+        anyhow::bail!(
+            "another niritiling instance is already listening on {}",
+            path.display()
+        );
+    }
+    // A socket file left behind by a previous run would make bind fail.
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("binding control socket {}", path.display()))?;
+
+    std::thread::Builder::new()
+        .name("control".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .context("accepting control connection")
+                    .and_then(|stream| handle_client(stream, &stats, &event_loop));
+                if let Err(e) = result {
+                    warn!("control connection failed: {:?}", e);
+                }
+            }
+        })
+        .context("spawning control socket thread")?;
+
+    debug!("control socket listening on {}", path.display());
+    Ok(())
+}
+
+fn handle_client<T: From<LoopCommand>>(
+    stream: UnixStream,
+    stats: &Stats,
+    event_loop: &LoopSender<T>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let mut command = String::new();
+    reader.read_line(&mut command)?;
+    let reply: ControlReply = match command.trim() {
+        "status" => serde_json::to_value(stats.report()).map_err(|e| e.to_string()),
+        command @ "monocle" => forward(event_loop, command),
+        other => Err(format!("unknown command {:?}", other)),
+    };
+
+    let mut buf = serde_json::to_string(&reply)?;
+    buf.push('\n');
+    reader.get_mut().write_all(buf.as_bytes())?;
+    Ok(())
+}
+
+/// Hands a command to the event loop and waits for its answer.
+fn forward<T: From<LoopCommand>>(event_loop: &LoopSender<T>, name: &str) -> ControlReply {
+    let (reply, answer) = mpsc::channel();
+    let command = LoopCommand {
+        name: name.to_string(),
+        reply,
+    };
+    let sent = match &*event_loop.lock().unwrap() {
+        Some(sender) => sender.send(T::from(command)).is_ok(),
+        None => false,
+    };
+    if !sent {
+        return Err("not connected to niri".to_string());
+    }
+    match answer.recv_timeout(CLIENT_TIMEOUT) {
+        Ok(reply) => reply,
+        Err(RecvTimeoutError::Timeout) => Err("timed out waiting for the event loop".to_string()),
+        Err(RecvTimeoutError::Disconnected) => Err("lost connection to niri".to_string()),
+    }
+}
+
+/// Sends a single command to a running niritiling instance and returns its reply.
+pub fn send_command(command: &str) -> Result<ControlReply> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("connecting to niritiling at {}", path.display()))?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).context("parsing reply from niritiling")
+}
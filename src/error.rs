@@ -0,0 +1,82 @@
+use crate::niri::Action;
+use std::fmt;
+
+pub type NiriResult<T> = std::result::Result<T, NiriError>;
+
+/// Errors returned by a [`NiriConnection`](crate::connection::NiriConnection).
+///
+/// The event loop decides whether to reconnect or carry on based on the variant, see
+/// [`NiriError::requires_reconnect`] and [`NiriError::is_transient`].
+#[derive(Debug)]
+pub enum NiriError {
+    /// Reading from or writing to the niri socket failed.
+    Transport {
+        context: &'static str,
+        source: std::io::Error,
+    },
+    /// niri did not answer a request within the IPC timeout.
+    ///
+    /// The reply may still arrive later and would be mistaken for the answer to the next request,
+    /// so the connection has to be re-established.
+    Timeout { context: &'static str },
+    /// niri understood the request but refused to perform the action.
+    Rejected {
+        action: Box<Action>,
+        message: String,
+    },
+    /// niri replied with a response that does not match the request.
+    UnexpectedResponse {
+        request: &'static str,
+        response: String,
+    },
+    /// The replies to separate requests disagree with each other, e.g. a window refers to a
+    /// workspace that was not part of the workspace list.
+    InconsistentState(String),
+}
+
+impl NiriError {
+    pub fn transport(context: &'static str, source: std::io::Error) -> Self {
+        Self::Transport { context, source }
+    }
+
+    /// Whether the connection is unusable and has to be re-established.
+    pub fn requires_reconnect(&self) -> bool {
+        matches!(self, Self::Transport { .. } | Self::Timeout { .. })
+    }
+
+    /// Whether repeating the same request shortly afterwards is likely to succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::InconsistentState(_))
+    }
+}
+
+impl fmt::Display for NiriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport { context, source } => {
+                write!(f, "niri socket error while {}: {}", context, source)
+            }
+            Self::Timeout { context } => write!(f, "timed out waiting for niri while {}", context),
+            Self::Rejected { action, message } => {
+                write!(f, "niri rejected action {:?}: {}", action, message)
+            }
+            Self::UnexpectedResponse { request, response } => {
+                write!(
+                    f,
+                    "unexpected response to {} request: {}",
+                    request, response
+                )
+            }
+            Self::InconsistentState(msg) => write!(f, "inconsistent niri state: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for NiriError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,70 +1,316 @@
 use anyhow::{Context, Result};
-use log::{error, info};
-use niri_ipc::socket::Socket;
-use niri_ipc::{Event, Request};
+use log::{debug, error, info, warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::Instant;
 
+mod backoff;
+mod config;
 mod connection;
+mod control;
+mod error;
 mod manager;
+mod niri;
+mod niri_config;
+mod status;
 
 #[cfg(test)]
 mod tests;
 
-use crate::connection::SocketConnection;
+use crate::backoff::Backoff;
+use crate::config::Config;
+use crate::connection::{EventStream, NiriConnection, SocketConnection, discover_socket};
+use crate::control::{LoopCommand, LoopSender};
+use crate::error::NiriResult;
 use crate::manager::NiriContext;
+use crate::niri::Event;
+use crate::status::Stats;
+
+const USAGE: &str = "usage: niritiling [--config <path>] [--niri-config <path>] [status | monocle]";
+
+#[derive(Debug, Default)]
+struct Args {
+    config: Option<PathBuf>,
+    niri_config: Option<PathBuf>,
+    command: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self::default();
+        let mut iter = std::env::args_os().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.to_str() {
+                Some("--config") => {
+                    let path = iter
+                        .next()
+                        .with_context(|| format!("--config requires a path\n{USAGE}"))?;
+                    args.config = Some(PathBuf::from(path));
+                }
+                Some("--niri-config") => {
+                    let path = iter
+                        .next()
+                        .with_context(|| format!("--niri-config requires a path\n{USAGE}"))?;
+                    args.niri_config = Some(PathBuf::from(path));
+                }
+                Some("-h" | "--help") => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                Some(command @ ("status" | "monocle")) if args.command.is_none() => {
+                    args.command = Some(command.to_string());
+                }
+                _ => anyhow::bail!("unexpected argument {:?}\n{USAGE}", arg),
+            }
+        }
+        Ok(args)
+    }
+}
 
 fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse()?;
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+
+    let mut config = Config::load(args.config.as_deref())?;
+    if args.niri_config.is_some() {
+        config.niri_config = args.niri_config;
+    }
     info!("niritiling: starting");
 
+    let stats = Arc::new(Stats::default());
+    let event_loop: LoopSender<LoopMessage> = LoopSender::default();
+    if let Err(e) = control::spawn_server(stats.clone(), event_loop.clone()) {
+        warn!(
+            "control socket unavailable, status will not be reported: {:?}",
+            e
+        );
+    }
+
+    let mut backoff = Backoff::default();
+    let mut context = None;
     loop {
-        if let Err(e) = run_event_loop() {
+        if let Err(e) = run_event_loop(&config, &stats, &event_loop, &mut backoff, &mut context) {
+            stats.set_connected(false);
+            let delay = backoff.next_delay();
             error!(
-                "fatal error in event loop: {:?}. attempting to reconnect in 5 seconds...",
-                e
+                "fatal error in event loop: {:?}. attempting to reconnect in {:.1} seconds...",
+                e,
+                delay.as_secs_f64()
             );
-            std::thread::sleep(std::time::Duration::from_secs(5));
+            std::thread::sleep(delay);
         } else {
             info!("event loop exited normally. restarting...");
         }
     }
 }
 
-fn run_event_loop() -> Result<()> {
-    let conn = SocketConnection::new()?;
-    let mut context = NiriContext::new(Box::new(conn));
-
-    let mut event_socket = Socket::connect().context("connecting to niri event stream")?;
-    let _ = event_socket
-        .send(Request::EventStream)
-        .context("failed to request event stream")?;
-    let mut read_event = event_socket.read_events();
-
-    info!("connected to niri; performing initial synchronization");
-    let state = context
-        .connection
-        .query_full_state()
-        .context("initial state query failed")?;
-    context.handle_event(Event::WindowsChanged {
-        windows: state.windows,
-    })?;
+/// Sends a command to the running daemon and prints its reply.
+fn run_command(command: &str) -> Result<()> {
+    match control::send_command(command)? {
+        Ok(payload) => {
+            println!("{}", serde_json::to_string_pretty(&payload)?);
+            Ok(())
+        }
+        Err(message) => anyhow::bail!("niritiling: {}", message),
+    }
+}
+
+/// What the event loop waits for: events from niri and commands from the control socket.
+enum LoopMessage {
+    Event(NiriResult<Event>),
+    Command(LoopCommand),
+}
+
+impl From<LoopCommand> for LoopMessage {
+    fn from(command: LoopCommand) -> Self {
+        Self::Command(command)
+    }
+}
+
+fn run_event_loop(
+    config: &Config,
+    stats: &Arc<Stats>,
+    event_loop: &LoopSender<LoopMessage>,
+    backoff: &mut Backoff,
+    context_slot: &mut Option<NiriContext>,
+) -> Result<()> {
+    let socket_path = discover_socket()?;
+    let mut conn = SocketConnection::connect(&socket_path, config.ipc_timeout(), stats.clone())?;
+    let niri_version = match conn.check_version() {
+        Ok(version) => version,
+        Err(e) if e.requires_reconnect() => return Err(e.into()),
+        Err(e) => {
+            warn!("version handshake failed: {}", e);
+            None
+        }
+    };
+    let conn: Box<dyn NiriConnection> = Box::new(conn);
+
+    // The context outlives connections so tracked positions and debounce state survive a
+    // reconnect, but not a niri restart, which hands out window ids anew.
+    let reconnecting = context_slot.is_some();
+    if let Some(ctx) = context_slot
+        && ctx.socket_path.as_ref() != Some(&socket_path)
+    {
+        info!(
+            "niri now listens on {}, starting over with a fresh state",
+            socket_path.display()
+        );
+        *context_slot = None;
+    }
+    let context = match context_slot {
+        Some(ctx) => {
+            ctx.replace_connection(conn);
+            ctx
+        }
+        None => {
+            let context = context_slot.insert(NiriContext::new(conn, config.clone()));
+            context.socket_path = Some(socket_path.clone());
+            context.load_niri_config();
+            context
+        }
+    };
+    if reconnecting {
+        stats.record_reconnect();
+    }
+
+    let mut event_stream = EventStream::connect(&socket_path)?;
+    if let Some(version) = niri_version {
+        event_stream.set_niri_version(version);
+    }
+
+    info!("connected to niri; synchronizing state");
+    context
+        .reconcile()
+        .context("initial synchronization failed")?;
+    backoff.reset();
+    stats.set_connected(true);
+
+    let (sender, messages) = mpsc::channel();
+    spawn_event_reader(event_stream, sender.clone())?;
+    *event_loop.lock().unwrap() = Some(sender);
 
     loop {
-        let event = match read_event().context("reading event from niri") {
-            Ok(ev) => ev,
-            Err(e) => {
+        let received = match config.output_poll_interval() {
+            Some(interval) => match messages.recv_timeout(interval) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => {
+                    handle_evaluation_result(context.poll_outputs())?;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("event reader thread stopped")
+                }
+            },
+            None => messages.recv().context("event reader thread stopped")?,
+        };
+        let event = match received {
+            LoopMessage::Event(Ok(ev)) => ev,
+            LoopMessage::Command(command) => {
+                handle_command(context, command)?;
+                continue;
+            }
+            LoopMessage::Event(Err(e)) => {
                 error!(
                     "error reading from event socket: {:?}. triggering reconnection...",
                     e
                 );
-                return Err(e);
+                return Err(e).context("reading event from niri");
             }
         };
+        context.apply_event(event);
 
-        if let Err(e) = context.handle_event(event) {
-            error!("error handling event: {:?}", e);
-            if e.to_string().contains("connection") || e.to_string().contains("socket") {
-                return Err(e);
+        // Keep gathering events for the coalescing window so a burst is evaluated once per
+        // workspace against the settled layout.
+        let deadline = Instant::now() + config.coalesce_window();
+        let mut coalesced = 0usize;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match messages.recv_timeout(timeout) {
+                Ok(LoopMessage::Event(Ok(event))) => {
+                    context.apply_event(event);
+                    coalesced += 1;
+                }
+                Ok(LoopMessage::Command(command)) => handle_command(context, command)?,
+                Ok(LoopMessage::Event(Err(e))) => {
+                    error!(
+                        "error reading from event socket: {:?}. triggering reconnection...",
+                        e
+                    );
+                    return Err(e).context("reading event from niri");
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("event reader thread stopped")
+                }
             }
         }
+        stats.record_events(coalesced as u64 + 1);
+        if coalesced > 0 {
+            debug!("coalesced {} events into one evaluation", coalesced);
+        }
+
+        handle_evaluation_result(context.process_pending())?;
     }
 }
+
+/// Carries out a command from the control socket and answers it.
+fn handle_command(context: &mut NiriContext, command: LoopCommand) -> Result<()> {
+    let result = match command.name.as_str() {
+        "monocle" => context.toggle_monocle().map(|toggled| match toggled {
+            Some((workspace, mode)) => serde_json::json!({ "workspace": workspace, "mode": mode }),
+            None => serde_json::Value::Null,
+        }),
+        other => {
+            let message = format!("unknown command {:?}", other);
+            command.reply(Err(message));
+            return Ok(());
+        }
+    };
+    match result {
+        Ok(payload) => {
+            command.reply(Ok(payload));
+            Ok(())
+        }
+        Err(e) => {
+            command.reply(Err(e.to_string()));
+            handle_evaluation_result(Err(e))
+        }
+    }
+}
+
+/// Logs evaluation errors that leave the connection usable and propagates the rest.
+fn handle_evaluation_result(result: NiriResult<()>) -> Result<()> {
+    if let Err(e) = result {
+        if e.requires_reconnect() {
+            error!("lost connection to niri: {}. triggering reconnection...", e);
+            return Err(e.into());
+        }
+        warn!("error handling event: {}", e);
+    }
+    Ok(())
+}
+
+/// Reads the event stream on a dedicated thread so slow evaluations never back up the socket.
+///
+/// The thread forwards the first read error and exits; it also exits once the receiver is dropped.
+fn spawn_event_reader(mut event_stream: EventStream, tx: Sender<LoopMessage>) -> Result<()> {
+    std::thread::Builder::new()
+        .name("niri-events".to_string())
+        .spawn(move || {
+            loop {
+                let result = event_stream.next_event();
+                let failed = result.is_err();
+                if tx.send(LoopMessage::Event(result)).is_err() || failed {
+                    break;
+                }
+            }
+        })
+        .context("spawning event reader thread")?;
+
+    Ok(())
+}
@@ -1,71 +1,254 @@
+use crate::config::{ColumnWidth, Config, LayoutConfig, Mode, OverflowTarget, ViewportPolicy};
 use crate::connection::{NiriConnection, NiriState, WindowPosition};
-use anyhow::Result;
-use log::{debug, error, info};
-use niri_ipc::{Action, Event, Window};
-use std::collections::HashMap;
+use crate::error::{NiriError, NiriResult};
+use crate::niri::{Action, ColumnDisplay, Event, Window};
+use crate::niri_config;
+use log::{debug, error, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
 
-const MAXIMIZED_RATIO_THRESHOLD: f64 = 0.9;
+#[cfg(test)]
+const SETTLE_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+const STATE_QUERY_ATTEMPTS: u32 = 3;
+const STATE_QUERY_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+/// How far, in logical pixels, a column may be off the calibrated maximized width and still count
+/// as maximized. Covers rounding at fractional scales.
+const CALIBRATION_TOLERANCE: f64 = 1.0;
+/// Widest a learned maximized width may be, relative to the one the layout predicts. Together
+/// with `unmaximized_ratio` this keeps widths of windows that didn't really get maximized, e.g.
+/// because of a size limit, from being learned.
+const CALIBRATION_MAX_RATIO: f64 = 1.05;
+
+/// A column of the scrolling layout, treated as a unit: all its tiles (or tabs) share one width.
+struct Column<'a> {
+    index: usize,
+    /// The topmost tile, which stands in for the column when it is resized.
+    representative: &'a Window,
+    /// The tiles from top to bottom.
+    windows: Vec<&'a Window>,
+    /// Number of tiles, from the highest tile index.
+    tile_count: usize,
+    /// Width of the widest tile. Tiles narrower than their column, e.g. windows with a maximum
+    /// size, don't make a maximized column look unmaximized.
+    width: f64,
+}
+
+impl<'a> Column<'a> {
+    /// Groups the windows that have a place in the scrolling layout into columns, ordered from
+    /// left to right.
+    fn collect(windows: &[&'a Window]) -> Vec<Self> {
+        let mut columns: BTreeMap<usize, Self> = BTreeMap::new();
+        for &w in windows {
+            let Some((index, tile)) = w.layout.pos_in_scrolling_layout else {
+                continue;
+            };
+            let width = w.layout.tile_size.0;
+            let column = columns.entry(index).or_insert(Self {
+                index,
+                representative: w,
+                windows: Vec::new(),
+                tile_count: 0,
+                width,
+            });
+            column.windows.push(w);
+            column.tile_count = column.tile_count.max(tile + 1);
+            column.width = column.width.max(width);
+        }
+        for column in columns.values_mut() {
+            column
+                .windows
+                .sort_by_key(|w| (w.layout.pos_in_scrolling_layout, w.id));
+            column.representative = column.windows[0];
+        }
+        columns.into_values().collect()
+    }
+}
 
 pub struct NiriContext {
     pub connection: Box<dyn NiriConnection>,
+    /// Socket of the niri instance the tracked window ids belong to. niri names it after its pid,
+    /// so a restarted niri listens on a different one.
+    pub socket_path: Option<PathBuf>,
+    pub config: Config,
+    /// `config.layout` with the settings left out there filled in from niri's config.
+    pub layout: LayoutConfig,
+    /// niri config file the layout settings were inherited from, re-read when niri reloads it.
+    niri_config_path: Option<PathBuf>,
     pub tracked_window_positions: HashMap<u64, WindowPosition>,
     pub debounced_maximize_state: HashMap<u64, (bool, std::time::Instant)>,
+    /// Last maximized decision per window, kept for the hysteresis in [`Self::is_maximized`].
+    pub observed_maximized: HashMap<u64, bool>,
+    /// Width of each window we maximized, from just before, to go back to when un-maximizing.
+    widths_before_maximize: HashMap<u64, ColumnWidth>,
+    /// Modes switched at runtime, by workspace id.
+    mode_overrides: HashMap<u64, Mode>,
+    /// Windows opened since the last evaluation, which may be stacked to honor `max_columns`.
+    newly_opened: HashSet<u64>,
+    /// Windows we stacked into another column to honor `max_columns`.
+    overflow_stacked: HashSet<u64>,
+    /// Proportion we last gave the column of each window in accordion or dwindle mode.
+    column_proportions: HashMap<u64, f64>,
+    /// Number of tiles in each window's column at the last evaluation.
+    column_tile_counts: HashMap<u64, usize>,
+    /// Windows in columns we switched to tabbed display because they got crowded.
+    auto_tabbed_windows: HashSet<u64>,
+    pub tracked_workspace_outputs: HashMap<u64, String>,
+    pub tracked_output_widths: HashMap<String, f64>,
+    /// Exact width niri gave a maximized column on each output, learned from the layout change
+    /// following our own maximize.
+    pub calibrated_widths: HashMap<String, f64>,
+    /// Windows we just maximized, with their output, their width before the action and the
+    /// maximized width the layout predicts, waiting for the layout change that reveals the
+    /// actual maximized width.
+    pending_calibrations: HashMap<u64, (String, f64, f64)>,
+    pending_workspaces: BTreeSet<u64>,
+    pending_closed_positions: Vec<WindowPosition>,
+    output_check_requested: bool,
+    overview_open: bool,
 }
 
 impl NiriContext {
-    pub fn new(connection: Box<dyn NiriConnection>) -> Self {
+    pub fn new(connection: Box<dyn NiriConnection>, config: Config) -> Self {
         Self {
             connection,
+            socket_path: None,
+            layout: config.layout.clone(),
+            niri_config_path: None,
+            config,
             tracked_window_positions: HashMap::new(),
             debounced_maximize_state: HashMap::new(),
+            observed_maximized: HashMap::new(),
+            widths_before_maximize: HashMap::new(),
+            mode_overrides: HashMap::new(),
+            newly_opened: HashSet::new(),
+            overflow_stacked: HashSet::new(),
+            column_proportions: HashMap::new(),
+            column_tile_counts: HashMap::new(),
+            auto_tabbed_windows: HashSet::new(),
+            tracked_workspace_outputs: HashMap::new(),
+            tracked_output_widths: HashMap::new(),
+            calibrated_widths: HashMap::new(),
+            pending_calibrations: HashMap::new(),
+            pending_workspaces: BTreeSet::new(),
+            pending_closed_positions: Vec::new(),
+            output_check_requested: false,
+            overview_open: false,
         }
     }
 
-    fn send_action(&mut self, action: Action) -> Result<()> {
+    /// Swaps in a new connection to the same niri instance, keeping everything learned so far.
+    ///
+    /// Follow up with [`Self::reconcile`] to catch up on what changed while disconnected. After
+    /// niri restarted, start over with a new context instead: window ids start from scratch, so
+    /// state kept under the old ids would be applied to unrelated windows.
+    pub fn replace_connection(&mut self, connection: Box<dyn NiriConnection>) {
+        self.connection = connection;
+        // The event stream reports the current overview state right after connecting.
+        self.overview_open = false;
+    }
+
+    /// Inherits the layout settings not configured for niritiling from niri's `config.kdl`.
+    ///
+    /// Problems with the file are only logged; niritiling then falls back to its own settings.
+    pub fn load_niri_config(&mut self) {
+        let path = self
+            .niri_config_path
+            .clone()
+            .or_else(|| self.config.niri_config.clone());
+        match niri_config::load(path.as_deref()) {
+            Ok(Some((path, niri_layout))) => {
+                let mut layout = self.config.layout.clone();
+                layout.inherit(&niri_layout);
+                self.layout = layout;
+                self.niri_config_path = Some(path);
+            }
+            Ok(None) => debug!("no niri config found, using built-in layout defaults"),
+            Err(e) => warn!("ignoring niri config: {:?}", e),
+        }
+    }
+
+    fn send_action(&mut self, action: Action) -> NiriResult<()> {
         self.connection.send_action(action)
     }
 
-    fn query_focused_window(&mut self) -> Result<Option<u64>> {
+    fn query_focused_window(&mut self) -> NiriResult<Option<u64>> {
         self.connection.query_focused_window()
     }
 
-    fn query_full_state(&mut self) -> Result<NiriState> {
-        self.connection.query_full_state()
+    fn query_full_state(&mut self) -> NiriResult<NiriState> {
+        let mut attempt = 1;
+        loop {
+            match self.connection.query_full_state() {
+                Err(e) if e.is_transient() && attempt < STATE_QUERY_ATTEMPTS => {
+                    debug!("state query attempt {} failed: {}; retrying", attempt, e);
+                    attempt += 1;
+                    std::thread::sleep(STATE_QUERY_RETRY_DELAY);
+                }
+                result => return result,
+            }
+        }
     }
 
-    fn is_maximized(
-        &self,
-        window_id: u64,
-        state: &NiriState,
-        windows_map: &HashMap<u64, &Window>,
-    ) -> bool {
-        if let Some(w) = windows_map.get(&window_id) {
-            if let Some(ws_id) = w.workspace_id {
-                if let Some(output_name) = state.ws_outputs.get(&ws_id) {
-                    if let Some(&output_width) = state.output_widths.get(output_name) {
-                        if output_width <= 0.0 {
-                            return false;
-                        }
-                        let tile_width = w.layout.tile_size.0;
-                        let ratio = tile_width / output_width;
-                        debug!(
-                            "window {} tile_width={:.0} output_width={:.0} ratio={:.2}",
-                            window_id, tile_width, output_width, ratio
-                        );
-                        return ratio > MAXIMIZED_RATIO_THRESHOLD;
-                    }
-                }
+    fn is_maximized(&mut self, column: &Column, ws_id: u64, state: &NiriState) -> bool {
+        let window_id = column.representative.id;
+        if let Some(output_name) = state.ws_outputs.get(&ws_id)
+            && let Some(&output_width) = state.output_widths.get(output_name)
+        {
+            let column_width = column.width;
+            if let Some(&calibrated) = self.calibrated_widths.get(output_name) {
+                let maximized = (column_width - calibrated).abs() <= CALIBRATION_TOLERANCE;
+                debug!(
+                    "column {} (window {}) width={:.0} calibrated_width={:.0} maximized={}",
+                    column.index, window_id, column_width, calibrated, maximized
+                );
+                self.observed_maximized.insert(window_id, maximized);
+                return maximized;
+            }
+
+            let maximized_width = self.layout.maximized_width(output_width);
+            if maximized_width <= 0.0 {
+                return false;
             }
+            let ratio = column_width / maximized_width;
+            let previously = self.observed_maximized.get(&window_id).copied();
+            let maximized = self.layout.is_maximized_ratio(ratio, previously);
+            debug!(
+                "column {} (window {}) width={:.0} maximized_width={:.0} ratio={:.2} maximized={}",
+                column.index, window_id, column_width, maximized_width, ratio, maximized
+            );
+            self.observed_maximized.insert(window_id, maximized);
+            return maximized;
         }
         false
     }
 
-    fn perform_maximize_action(
-        &mut self,
-        target_window_id: u64,
-        restore_focus: bool,
-    ) -> Result<()> {
-        let original_focus = self.query_focused_window().ok().flatten();
+    /// Resizes the column of `target_window_id` without touching focus.
+    ///
+    /// Only if niri refuses the window-targeted resize does this fall back to focusing the window,
+    /// resizing the focused column and focusing the original window again.
+    fn resize_column_of(&mut self, target_window_id: u64, width: ColumnWidth) -> NiriResult<()> {
+        // Whatever proportion we gave the column last is overridden now.
+        self.column_proportions.remove(&target_window_id);
+        let change = width.size_change();
+        match self.send_action(Action::SetWindowWidth {
+            id: Some(target_window_id),
+            change,
+        }) {
+            Err(NiriError::Rejected { message, .. }) => {
+                warn!(
+                    "niri refused to resize window {} directly ({}), resizing through focus",
+                    target_window_id, message
+                );
+            }
+            result => return result,
+        }
+        self.act_on_column_of(target_window_id, Action::SetColumnWidth { change })
+    }
+
+    /// Sends an action that only works on the focused column to the column of
+    /// `target_window_id`, by focusing it first and focusing the original window again after.
+    fn act_on_column_of(&mut self, target_window_id: u64, action: Action) -> NiriResult<()> {
+        let original_focus = best_effort(self.query_focused_window())?.flatten();
 
         if original_focus != Some(target_window_id) {
             self.send_action(Action::FocusWindow {
@@ -73,25 +256,300 @@ impl NiriContext {
             })?;
         }
 
-        self.send_action(Action::MaximizeColumn {})?;
+        self.send_action(action)?;
 
-        if restore_focus {
-            if let Some(orig_id) = original_focus {
-                if orig_id != target_window_id {
-                    debug!("restoring focus to {}", orig_id);
-                    let _ = self.send_action(Action::FocusWindow { id: orig_id });
-                }
-            }
+        if let Some(orig_id) = original_focus
+            && orig_id != target_window_id
+        {
+            debug!("restoring focus to {}", orig_id);
+            best_effort(self.send_action(Action::FocusWindow { id: orig_id }))?;
         }
         Ok(())
     }
 
-    pub fn evaluate_workspace(
+    /// Mode of a workspace: the one toggled at runtime, else the configured one.
+    fn mode_of(&self, ws_id: u64, state: &NiriState) -> Mode {
+        let mode = self.mode_overrides.get(&ws_id).copied().unwrap_or_else(|| {
+            self.config
+                .mode_of(state.ws_names.get(&ws_id).map(String::as_str))
+        });
+        // Dwindle would split the single column of a portrait output up again.
+        if mode == Mode::Dwindle && self.is_stacking_portrait(ws_id, state) {
+            return Mode::Maximize;
+        }
+        mode
+    }
+
+    /// Whether the workspace is on a portrait output and `stack_on_portrait` applies to it.
+    fn is_stacking_portrait(&self, ws_id: u64, state: &NiriState) -> bool {
+        if !self.config.stack_on_portrait {
+            return false;
+        }
+        let Some(output) = state.ws_outputs.get(&ws_id) else {
+            return false;
+        };
+        match (
+            state.output_widths.get(output),
+            state.output_heights.get(output),
+        ) {
+            (Some(width), Some(height)) => height > width,
+            _ => false,
+        }
+    }
+
+    /// Switches the focused workspace into monocle mode, or out of it back to its configured
+    /// mode, and re-evaluates it.
+    ///
+    /// Returns the workspace and its new mode, or `None` if no workspace is focused.
+    pub fn toggle_monocle(&mut self) -> NiriResult<Option<(u64, Mode)>> {
+        let state = self.query_full_state()?;
+        let Some(ws_id) = state.focused_workspace else {
+            return Ok(None);
+        };
+
+        let configured = self
+            .config
+            .mode_of(state.ws_names.get(&ws_id).map(String::as_str));
+        let mode = match self.mode_of(ws_id, &state) {
+            Mode::Monocle if configured == Mode::Monocle => Mode::Maximize,
+            Mode::Monocle => configured,
+            _ => Mode::Monocle,
+        };
+        if mode == configured {
+            self.mode_overrides.remove(&ws_id);
+        } else {
+            self.mode_overrides.insert(ws_id, mode);
+        }
+        for w in state
+            .windows
+            .iter()
+            .filter(|w| w.workspace_id == Some(ws_id))
+        {
+            self.column_proportions.remove(&w.id);
+        }
+        info!("workspace {}: switched to {:?} mode", ws_id, mode);
+
+        self.pending_workspaces.insert(ws_id);
+        if !self.overview_open {
+            self.evaluate_pending(&state)?;
+        }
+        Ok(Some((ws_id, mode)))
+    }
+
+    /// Stacks windows that just opened a column beyond the workspace's `max_columns` into an
+    /// existing column, and expels windows stacked that way again once columns have closed.
+    /// Workspaces on portrait outputs are limited to a single column.
+    ///
+    /// Returns whether the layout was changed.
+    fn enforce_column_limit(
         &mut self,
         ws_id: u64,
         state: &NiriState,
-        windows_map: &HashMap<u64, &Window>,
-    ) -> Result<()> {
+        columns: &[Column],
+    ) -> NiriResult<bool> {
+        let ws_name = state.ws_names.get(&ws_id).map(String::as_str);
+        let max_columns = if self.is_stacking_portrait(ws_id, state) {
+            Some(1)
+        } else {
+            self.config.max_columns_of(ws_name)
+        };
+        let Some(max_columns) = max_columns else {
+            return Ok(false);
+        };
+        let (Some(first), Some(last)) = (columns.first(), columns.last()) else {
+            return Ok(false);
+        };
+        let (first_index, last_index) = (first.index, last.index);
+        let mut changed = false;
+
+        if columns.len() > max_columns {
+            let mut excess = columns.len() - max_columns;
+            for column in columns {
+                if excess == 0 {
+                    break;
+                }
+                let &[window] = column.windows.as_slice() else {
+                    continue;
+                };
+                if !self.newly_opened.contains(&window.id) {
+                    continue;
+                }
+
+                info!(
+                    "workspace {}: window {} would make {} columns (max {}) -> stacking it",
+                    ws_id,
+                    window.id,
+                    columns.len(),
+                    max_columns
+                );
+                let id = Some(window.id);
+                match self.config.overflow_into {
+                    OverflowTarget::Last => {
+                        if column.index != last_index {
+                            self.act_on_column_of(window.id, Action::MoveColumnToLast {})?;
+                        }
+                        self.send_action(Action::ConsumeOrExpelWindowLeft { id })?;
+                    }
+                    OverflowTarget::Focused if column.index == first_index => {
+                        self.send_action(Action::ConsumeOrExpelWindowRight { id })?;
+                    }
+                    OverflowTarget::Focused => {
+                        self.send_action(Action::ConsumeOrExpelWindowLeft { id })?;
+                    }
+                }
+                self.overflow_stacked.insert(window.id);
+                excess -= 1;
+                changed = true;
+            }
+        } else if columns.len() < max_columns {
+            let mut room = max_columns - columns.len();
+            let stacked = columns
+                .iter()
+                .filter(|c| c.windows.len() > 1)
+                .flat_map(|c| c.windows.iter())
+                .filter(|w| self.overflow_stacked.contains(&w.id))
+                .map(|w| w.id)
+                .collect::<Vec<_>>();
+            for window_id in stacked {
+                if room == 0 {
+                    break;
+                }
+                info!(
+                    "workspace {}: room for {} more column(s) -> expelling window {}",
+                    ws_id, room, window_id
+                );
+                self.send_action(Action::ConsumeOrExpelWindowRight {
+                    id: Some(window_id),
+                })?;
+                self.overflow_stacked.remove(&window_id);
+                room -= 1;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Resets the tile heights of every column that gained or lost a tile since the last
+    /// evaluation, so its tiles share the height equally again. Dwindle mode always does this.
+    fn equalize_tile_heights(
+        &mut self,
+        ws_id: u64,
+        columns: &[Column],
+        mode: Mode,
+    ) -> NiriResult<()> {
+        let enabled = self.config.equalize_tile_heights || mode == Mode::Dwindle;
+        for column in columns {
+            let changed = column.windows.iter().any(|w| {
+                self.column_tile_counts
+                    .get(&w.id)
+                    .is_some_and(|&count| count != column.tile_count)
+            });
+            for w in &column.windows {
+                self.column_tile_counts.insert(w.id, column.tile_count);
+            }
+            if !changed || column.tile_count < 2 || !enabled {
+                continue;
+            }
+
+            info!(
+                "workspace {}: column {} now has {} tiles -> equalizing their heights",
+                ws_id, column.index, column.tile_count
+            );
+            for w in &column.windows {
+                self.send_action(Action::ResetWindowHeight { id: Some(w.id) })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches columns with more tiles than `auto_tab_threshold` to tabbed display, and columns
+    /// we switched back to normal once they have shrunk again.
+    ///
+    /// niri doesn't report a column's display mode, so only columns in
+    /// [`Self::auto_tabbed_windows`] are ever switched back; columns the user tabbed stay tabbed.
+    fn update_tabbed_columns(&mut self, ws_id: u64, columns: &[Column]) -> NiriResult<()> {
+        let threshold = self.config.auto_tab_threshold;
+        if threshold == 0 {
+            return Ok(());
+        }
+
+        for column in columns {
+            let converted = column
+                .windows
+                .iter()
+                .any(|w| self.auto_tabbed_windows.contains(&w.id));
+            let crowded = column.tile_count > threshold;
+            if crowded == converted {
+                // Windows that joined a converted column belong to it as well.
+                if converted {
+                    self.auto_tabbed_windows
+                        .extend(column.windows.iter().map(|w| w.id));
+                }
+                continue;
+            }
+
+            let display = if crowded {
+                info!(
+                    "workspace {}: column {} has {} tiles -> switching to tabbed display",
+                    ws_id, column.index, column.tile_count
+                );
+                self.auto_tabbed_windows
+                    .extend(column.windows.iter().map(|w| w.id));
+                ColumnDisplay::Tabbed
+            } else {
+                info!(
+                    "workspace {}: column {} is down to {} tiles -> switching back to normal display",
+                    ws_id, column.index, column.tile_count
+                );
+                for w in &column.windows {
+                    self.auto_tabbed_windows.remove(&w.id);
+                }
+                ColumnDisplay::Normal
+            };
+            self.act_on_column_of(
+                column.representative.id,
+                Action::SetColumnDisplay { display },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Widens the column of `target_window` to the full width of its output, remembering its
+    /// current width for [`Self::perform_unmaximize_action`].
+    fn perform_maximize_action(&mut self, target_window: &Window) -> NiriResult<()> {
+        let width_before = f64::from(target_window.layout.window_size.0);
+        if width_before > 0.0 {
+            self.widths_before_maximize
+                .insert(target_window.id, ColumnWidth::Fixed(width_before));
+        }
+        self.resize_column_of(target_window.id, ColumnWidth::Proportion(1.0))
+    }
+
+    /// Shrinks the column of `target_window_id` back from maximized: to the width it had before
+    /// we maximized it, else to the configured default column width, else to niri's built-in
+    /// default of half the output.
+    fn perform_unmaximize_action(&mut self, target_window_id: u64) -> NiriResult<()> {
+        let width = self
+            .widths_before_maximize
+            .remove(&target_window_id)
+            .or(self.layout.default_column_width)
+            .unwrap_or(ColumnWidth::Proportion(0.5));
+        self.resize_column_of(target_window_id, width)
+    }
+
+    /// Column of a tiled window, falling back to the remembered column while it is fullscreen.
+    fn column_of(&self, window: &Window) -> Option<usize> {
+        match window.layout.pos_in_scrolling_layout {
+            Some((col, _)) => Some(col),
+            None => self
+                .tracked_window_positions
+                .get(&window.id)
+                .filter(|p| p.fullscreen && Some(p.workspace_id) == window.workspace_id)
+                .and_then(|p| p.column),
+        }
+    }
+
+    pub fn evaluate_workspace(&mut self, ws_id: u64, state: &NiriState) -> NiriResult<()> {
         let tiled_windows: Vec<&Window> = state
             .windows
             .iter()
@@ -102,138 +560,301 @@ impl NiriContext {
             return Ok(());
         }
 
-        let mut unique_columns = std::collections::HashSet::new();
+        // Fullscreen windows have no position in the layout but keep their column, so they
+        // still count towards the columns of the workspace. They are never resized themselves.
+        let mut unique_columns = HashSet::new();
         for w in &tiled_windows {
-            if let Some((col_idx, _)) = w.layout.pos_in_scrolling_layout {
+            if let Some(col_idx) = self.column_of(w) {
                 unique_columns.insert(col_idx);
             }
         }
+        let columns = Column::collect(&tiled_windows);
+        if self.enforce_column_limit(ws_id, state, &columns)? {
+            // The columns are about to change; the resulting layout gets evaluated again.
+            return Ok(());
+        }
+        let mode = self.mode_of(ws_id, state);
+        self.update_tabbed_columns(ws_id, &columns)?;
+        self.equalize_tile_heights(ws_id, &columns, mode)?;
 
         let column_count = unique_columns.len();
+        let tile_count: usize = columns.iter().map(|c| c.windows.len()).sum();
 
         if column_count == 0 {
             return Ok(());
-        } else if column_count == 1 {
-            let win_id = tiled_windows[0].id;
-            if !self.is_maximized(win_id, state, windows_map) {
-                let now = std::time::Instant::now();
-                if let Some(&(target_maximized, last_time)) =
-                    self.debounced_maximize_state.get(&win_id)
-                {
-                    if target_maximized
-                        && now.duration_since(last_time) < std::time::Duration::from_millis(200)
-                    {
-                        debug!(
-                            "workspace {}: skipping maximize for window {} due to debounce",
-                            ws_id, win_id
-                        );
-                        return Ok(());
-                    }
-                }
-                self.debounced_maximize_state.insert(win_id, (true, now));
-
-                info!(
-                    "workspace {}: single column -> maximizing window {}",
-                    ws_id, win_id
+        } else if mode == Mode::Dwindle && tiled_windows.len() > 1 {
+            if tile_count < tiled_windows.len() {
+                debug!(
+                    "workspace {}: a window is fullscreen, leaving the dwindle layout alone",
+                    ws_id
                 );
-                self.perform_maximize_action(win_id, true)?;
+            } else {
+                self.apply_dwindle(ws_id, &columns)?;
+            }
+        } else if column_count > 1 && mode == Mode::Accordion {
+            self.apply_accordion(ws_id, state, &columns)?;
+        } else if column_count == 1 || mode == Mode::Monocle {
+            if columns.is_empty() {
+                debug!(
+                    "workspace {}: only fullscreen windows, leaving it alone",
+                    ws_id
+                );
+            }
+            for column in &columns {
+                self.maximize_column(ws_id, state, column)?;
             }
         } else {
-            let target_nudge_focus = self.query_focused_window().ok().flatten();
-            let mut cols_vec: Vec<usize> = unique_columns.into_iter().collect();
-            cols_vec.sort_unstable();
+            let target_nudge_focus = best_effort(self.query_focused_window())?.flatten();
 
             let mut did_unmaximize = false;
-            for &col_idx in &cols_vec {
-                if let Some(w) = tiled_windows
-                    .iter()
-                    .find(|w| w.layout.pos_in_scrolling_layout.map(|(c, _)| c) == Some(col_idx))
-                {
-                    if self.is_maximized(w.id, state, windows_map) {
-                        let now = std::time::Instant::now();
-                        if let Some(&(target_maximized, last_time)) =
-                            self.debounced_maximize_state.get(&w.id)
-                        {
-                            if !target_maximized
-                                && now.duration_since(last_time)
-                                    < std::time::Duration::from_millis(200)
-                            {
-                                debug!(
-                                    "workspace {}: skipping un-maximize for window {} due to debounce",
-                                    ws_id, w.id
-                                );
-                                continue;
-                            }
-                        }
-                        self.debounced_maximize_state.insert(w.id, (false, now));
-
-                        info!(
-                            "workspace {}: multiple columns -> un-maximizing window {} in column {}",
-                            ws_id, w.id, col_idx
+            for column in &columns {
+                let w = column.representative;
+                if self.is_maximized(column, ws_id, state) {
+                    let now = std::time::Instant::now();
+                    if let Some(&(target_maximized, last_time)) =
+                        self.debounced_maximize_state.get(&w.id)
+                        && !target_maximized
+                        && now.duration_since(last_time) < std::time::Duration::from_millis(200)
+                    {
+                        debug!(
+                            "workspace {}: skipping un-maximize for window {} due to debounce",
+                            ws_id, w.id
                         );
-                        self.perform_maximize_action(w.id, false)?;
-                        did_unmaximize = true;
+                        continue;
                     }
+                    self.debounced_maximize_state.insert(w.id, (false, now));
+
+                    info!(
+                        "workspace {}: multiple columns -> un-maximizing window {} in column {}",
+                        ws_id, w.id, column.index
+                    );
+                    self.perform_unmaximize_action(w.id)?;
+                    did_unmaximize = true;
                 }
             }
 
             if did_unmaximize {
-                debug!(
-                    "workspace {}: waiting for layout to settle before viewport nudge",
-                    ws_id
-                );
-                std::thread::sleep(std::time::Duration::from_millis(50));
+                if self.config.viewport != ViewportPolicy::Keep {
+                    debug!(
+                        "workspace {}: waiting for layout to settle before adjusting the viewport",
+                        ws_id
+                    );
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+                self.apply_viewport_policy(ws_id, target_nudge_focus)?;
+            }
+        }
+        Ok(())
+    }
 
-                debug!(
-                    "workspace {}: nudging viewport left (target focus: {:?})",
-                    ws_id, target_nudge_focus
+    /// Maximizes `column` unless it already is or we just un-maximized it.
+    fn maximize_column(
+        &mut self,
+        ws_id: u64,
+        state: &NiriState,
+        column: &Column,
+    ) -> NiriResult<()> {
+        let target = column.representative;
+        let win_id = target.id;
+        if self.is_maximized(column, ws_id, state) {
+            return Ok(());
+        }
+
+        let now = std::time::Instant::now();
+        if let Some(&(target_maximized, last_time)) = self.debounced_maximize_state.get(&win_id)
+            && target_maximized
+            && now.duration_since(last_time) < std::time::Duration::from_millis(200)
+        {
+            debug!(
+                "workspace {}: skipping maximize for window {} due to debounce",
+                ws_id, win_id
+            );
+            return Ok(());
+        }
+        self.debounced_maximize_state.insert(win_id, (true, now));
+
+        info!(
+            "workspace {}: maximizing window {} in column {}",
+            ws_id, win_id, column.index
+        );
+        if let Some(output) = state.ws_outputs.get(&ws_id)
+            && let Some(&output_width) = state.output_widths.get(output)
+            && !self.calibrated_widths.contains_key(output)
+        {
+            let predicted = self.layout.maximized_width(output_width);
+            self.pending_calibrations.insert(
+                win_id,
+                (output.clone(), target.layout.tile_size.0, predicted),
+            );
+        }
+        self.perform_maximize_action(target)
+    }
+
+    /// Makes the column with the focused window wide and every other column narrow.
+    ///
+    /// Widths are only set when they differ from what we set last, so moving focus only resizes
+    /// the columns that gained or lost it.
+    fn apply_accordion(
+        &mut self,
+        ws_id: u64,
+        state: &NiriState,
+        columns: &[Column],
+    ) -> NiriResult<()> {
+        let Some(focused_column) = state
+            .windows
+            .iter()
+            .find(|w| w.is_focused && w.workspace_id == Some(ws_id) && !w.is_floating)
+            .and_then(|w| w.layout.pos_in_scrolling_layout)
+            .map(|(col, _)| col)
+        else {
+            return Ok(());
+        };
+
+        let accordion = self.config.accordion;
+        for column in columns {
+            let proportion = if column.index == focused_column {
+                accordion.focused
+            } else {
+                accordion.others
+            };
+            self.set_column_proportion(ws_id, column, proportion)?;
+        }
+        Ok(())
+    }
+
+    /// Emulates a dwindle layout: each new window alternately opens a column taking half of the
+    /// remaining width, or splits the last column vertically.
+    ///
+    /// Windows are taken in layout order. The first gets a column of its own, every further
+    /// column holds two tiles (the last one possibly one), and each column is half as wide as the
+    /// one before it, except the last, which takes the rest. Only one window is moved per
+    /// evaluation; the layout change it causes brings us back here for the next one.
+    fn apply_dwindle(&mut self, ws_id: u64, columns: &[Column]) -> NiriResult<()> {
+        let windows = columns.iter().flat_map(|c| c.windows.iter().copied());
+        for (i, w) in windows.enumerate() {
+            let Some((_, tile)) = w.layout.pos_in_scrolling_layout else {
+                continue;
+            };
+            let starts_column = i == 0 || i % 2 == 1;
+            let id = Some(w.id);
+            if starts_column && tile > 0 {
+                info!(
+                    "workspace {}: dwindle -> moving window {} into a column of its own",
+                    ws_id, w.id
                 );
-                self.send_action(Action::FocusColumnLeft {})?;
-                if let Some(orig_id) = target_nudge_focus {
-                    debug!("workspace {}: restoring focus to {}", ws_id, orig_id);
-                    let _ = self.send_action(Action::FocusWindow { id: orig_id });
-                }
+                return self.send_action(Action::ConsumeOrExpelWindowRight { id });
             }
+            if !starts_column && tile == 0 {
+                info!(
+                    "workspace {}: dwindle -> stacking window {} into the column on its left",
+                    ws_id, w.id
+                );
+                return self.send_action(Action::ConsumeOrExpelWindowLeft { id });
+            }
+        }
+
+        let last = columns.len() - 1;
+        for (k, column) in columns.iter().enumerate() {
+            let halvings = if k == last { k } else { k + 1 };
+            let proportion = 0.5_f64.powi(halvings as i32);
+            self.set_column_proportion(ws_id, column, proportion)?;
         }
         Ok(())
     }
 
-    pub fn handle_event(&mut self, event: Event) -> Result<()> {
-        let mut affected_workspaces = Vec::new();
-        let mut closed_positions: Vec<WindowPosition> = Vec::new();
+    /// Resizes `column` to `proportion` of the output width, unless we already did.
+    fn set_column_proportion(
+        &mut self,
+        ws_id: u64,
+        column: &Column,
+        proportion: f64,
+    ) -> NiriResult<()> {
+        let id = column.representative.id;
+        if self.column_proportions.get(&id) == Some(&proportion) {
+            return Ok(());
+        }
+        debug!(
+            "workspace {}: column {} to {:.3} of the output",
+            ws_id, column.index, proportion
+        );
+        self.resize_column_of(id, ColumnWidth::Proportion(proportion))?;
+        self.column_proportions.insert(id, proportion);
+        Ok(())
+    }
+
+    /// Moves the viewport of the focused workspace according to the configured
+    /// [`ViewportPolicy`], then gives focus back to `focus`.
+    fn apply_viewport_policy(&mut self, ws_id: u64, focus: Option<u64>) -> NiriResult<()> {
+        let policy = self.config.viewport;
+        debug!(
+            "workspace {}: applying viewport policy {:?} (target focus: {:?})",
+            ws_id, policy, focus
+        );
+
+        // Even refocusing the focused window may scroll the view.
+        if policy == ViewportPolicy::Keep {
+            return Ok(());
+        }
+        match policy {
+            ViewportPolicy::NudgeLeft => self.send_action(Action::FocusColumnLeft {})?,
+            ViewportPolicy::SnapFirst => self.send_action(Action::FocusColumnFirst {})?,
+            ViewportPolicy::Keep | ViewportPolicy::CenterFocused | ViewportPolicy::Fit => {}
+        }
+        if let Some(orig_id) = focus {
+            debug!("workspace {}: restoring focus to {}", ws_id, orig_id);
+            best_effort(self.send_action(Action::FocusWindow { id: orig_id }))?;
+        }
+        match policy {
+            ViewportPolicy::CenterFocused => self.send_action(Action::CenterColumn {}),
+            ViewportPolicy::Fit => self.send_action(Action::CenterVisibleColumns {}),
+            ViewportPolicy::Keep | ViewportPolicy::NudgeLeft | ViewportPolicy::SnapFirst => Ok(()),
+        }
+    }
+
+    /// Applies an event and evaluates every workspace it touched after a short settle delay.
+    ///
+    /// The event loop batches events itself and calls [`Self::process_pending`] directly; this is
+    /// the single-event path the tests drive.
+    #[cfg(test)]
+    pub fn handle_event(&mut self, event: Event) -> NiriResult<()> {
+        self.apply_event(event);
+        if self.has_pending_work() {
+            std::thread::sleep(SETTLE_DELAY);
+        }
+        self.process_pending()
+    }
 
+    /// Updates the tracked window positions from an event and records the affected workspaces.
+    ///
+    /// No IPC happens here, so a burst of events can be applied cheaply before a single
+    /// [`Self::process_pending`] call evaluates the resulting state.
+    pub fn apply_event(&mut self, event: Event) {
         match event {
             Event::WindowsChanged { windows } => {
                 debug!("full windows change event received");
                 let mut new_tracked = HashMap::with_capacity(windows.len());
 
                 for w in windows {
-                    if !w.is_floating {
-                        if let Some(ws_id) = w.workspace_id {
-                            let (col, tile) = w
-                                .layout
-                                .pos_in_scrolling_layout
-                                .map(|(c, t)| (Some(c), Some(t)))
-                                .unwrap_or((None, None));
-
-                            let pos = WindowPosition {
-                                workspace_id: ws_id,
-                                column: col,
-                                tile,
-                            };
-                            new_tracked.insert(w.id, pos);
-                        }
+                    if !w.is_floating
+                        && let Some(ws_id) = w.workspace_id
+                    {
+                        let pos = WindowPosition::from_layout(
+                            ws_id,
+                            w.layout.pos_in_scrolling_layout,
+                            self.tracked_window_positions.get(&w.id),
+                        );
+                        new_tracked.insert(w.id, pos);
                     }
                 }
 
                 for (&id, &pos) in &new_tracked {
                     if self.tracked_window_positions.get(&id) != Some(&pos) {
-                        affected_workspaces.push(pos.workspace_id);
+                        self.pending_workspaces.insert(pos.workspace_id);
                     }
                 }
                 for (&id, &pos) in &self.tracked_window_positions {
                     if new_tracked.get(&id) != Some(&pos) {
-                        affected_workspaces.push(pos.workspace_id);
+                        self.pending_workspaces.insert(pos.workspace_id);
                     }
                 }
 
@@ -254,106 +875,327 @@ impl NiriContext {
                             "window {} became floating, re-evaluating ws {}",
                             id, pos.workspace_id
                         );
-                        affected_workspaces.push(pos.workspace_id);
+                        self.pending_workspaces.insert(pos.workspace_id);
                     }
                 } else if let Some(ws_id) = ws_id_opt {
-                    let (col, tile) = window
-                        .layout
-                        .pos_in_scrolling_layout
-                        .map(|(c, t)| (Some(c), Some(t)))
-                        .unwrap_or((None, None));
-
-                    let new_pos = WindowPosition {
-                        workspace_id: ws_id,
-                        column: col,
-                        tile,
-                    };
+                    let new_pos = WindowPosition::from_layout(
+                        ws_id,
+                        window.layout.pos_in_scrolling_layout,
+                        old_pos.as_ref(),
+                    );
+                    match (old_pos.is_some_and(|p| p.fullscreen), new_pos.fullscreen) {
+                        (false, true) => info!("window {} entered fullscreen", id),
+                        (true, false) => info!("window {} left fullscreen", id),
+                        _ => {}
+                    }
 
+                    if old_pos.is_none() {
+                        self.newly_opened.insert(id);
+                    }
                     self.tracked_window_positions.insert(id, new_pos);
                     debug!(
                         "window {} position updated to {:?}, re-evaluating",
                         id, new_pos
                     );
-                    affected_workspaces.push(ws_id);
-                    if let Some(old) = old_pos {
-                        if old.workspace_id != ws_id {
-                            affected_workspaces.push(old.workspace_id);
-                        }
+                    self.pending_workspaces.insert(ws_id);
+                    if let Some(old) = old_pos
+                        && old.workspace_id != ws_id
+                    {
+                        self.pending_workspaces.insert(old.workspace_id);
                     }
                 }
             }
 
             Event::WindowLayoutsChanged { changes } => {
                 for (id, layout) in changes {
+                    self.calibrate(id, layout.tile_size.0);
                     if let Some(pos) = self.tracked_window_positions.get_mut(&id) {
-                        let (col, tile) = layout
-                            .pos_in_scrolling_layout
-                            .map(|(c, t)| (Some(c), Some(t)))
-                            .unwrap_or((None, None));
+                        *pos = WindowPosition::from_layout(
+                            pos.workspace_id,
+                            layout.pos_in_scrolling_layout,
+                            Some(pos),
+                        );
 
                         debug!(
-                            "window {} layout updated to column {:?}, tile {:?}, re-evaluating ws {}",
-                            id, col, tile, pos.workspace_id
+                            "window {} layout updated to column {:?}, tile {:?}, fullscreen {}, re-evaluating ws {}",
+                            id, pos.column, pos.tile, pos.fullscreen, pos.workspace_id
                         );
-                        pos.column = col;
-                        pos.tile = tile;
-                        affected_workspaces.push(pos.workspace_id);
+                        self.pending_workspaces.insert(pos.workspace_id);
                     }
                 }
             }
 
             Event::WindowClosed { id } => {
+                self.observed_maximized.remove(&id);
+                self.widths_before_maximize.remove(&id);
+                self.auto_tabbed_windows.remove(&id);
+                self.column_tile_counts.remove(&id);
+                self.column_proportions.remove(&id);
+                self.newly_opened.remove(&id);
+                self.overflow_stacked.remove(&id);
+                self.pending_calibrations.remove(&id);
                 if let Some(pos) = self.tracked_window_positions.remove(&id) {
                     info!(
                         "window {} closed, re-evaluating ws {}",
                         id, pos.workspace_id
                     );
-                    affected_workspaces.push(pos.workspace_id);
-                    closed_positions.push(pos);
+                    self.pending_workspaces.insert(pos.workspace_id);
+                    self.pending_closed_positions.push(pos);
                 }
             }
 
+            Event::WorkspacesChanged { workspaces } => {
+                let outputs = workspaces
+                    .into_iter()
+                    .filter_map(|ws| Some((ws.id, ws.output?)))
+                    .collect();
+                self.update_workspace_outputs(outputs);
+            }
+
+            Event::WindowFocusChanged { id: Some(id) } if self.config.follows_focus() => {
+                if let Some(pos) = self.tracked_window_positions.get(&id) {
+                    debug!(
+                        "window {} focused, re-evaluating ws {}",
+                        id, pos.workspace_id
+                    );
+                    self.pending_workspaces.insert(pos.workspace_id);
+                }
+            }
+
+            Event::ConfigLoaded { failed: false } => {
+                // Output scale and mode can be changed from the config.
+                debug!("niri config reloaded, checking for output changes");
+                self.output_check_requested = true;
+                // Gaps and struts may have changed as well.
+                self.invalidate_calibrations(None);
+                self.load_niri_config();
+            }
+
+            Event::OverviewOpenedOrClosed { is_open } => {
+                if is_open {
+                    info!("overview opened, pausing evaluations");
+                } else {
+                    info!(
+                        "overview closed, evaluating {} touched workspace(s)",
+                        self.pending_workspaces.len()
+                    );
+                }
+                self.overview_open = is_open;
+            }
+
+            Event::WorkspaceActivated { id, .. } => {
+                // The event doesn't say where the workspace lives, so compare outputs against
+                // the next state query.
+                debug!("workspace {} activated, checking for output changes", id);
+                self.output_check_requested = true;
+            }
+
             _ => {}
         }
+    }
 
-        if !affected_workspaces.is_empty() {
-            affected_workspaces.sort_unstable();
-            affected_workspaces.dedup();
+    /// Learns the maximized width of an output from the first width change of a window we just
+    /// maximized there.
+    ///
+    /// A width too far off the predicted one is discarded, leaving the output to the ratio check.
+    fn calibrate(&mut self, window_id: u64, tile_width: f64) {
+        let Some((_, width_before, _)) = self.pending_calibrations.get(&window_id) else {
+            return;
+        };
+        // Layout changes that only move the window say nothing about the maximized width.
+        if *width_before == tile_width {
+            return;
+        }
+        let Some((output, _, predicted)) = self.pending_calibrations.remove(&window_id) else {
+            return;
+        };
+        let ratio = tile_width / predicted;
+        if !(self.layout.unmaximized_ratio..=CALIBRATION_MAX_RATIO).contains(&ratio) {
+            warn!(
+                "not calibrating maximized column width on {}: window {} became {:.0} wide, \
+                 expected about {:.0}",
+                output, window_id, tile_width, predicted
+            );
+            return;
+        }
+        info!(
+            "calibrated maximized column width on {} to {:.0}",
+            output, tile_width
+        );
+        self.calibrated_widths.insert(output, tile_width);
+    }
 
-            std::thread::sleep(std::time::Duration::from_millis(20));
+    /// Forgets the calibrated maximized width of `output`, or of every output if `None`.
+    fn invalidate_calibrations(&mut self, output: Option<&str>) {
+        match output {
+            Some(name) => {
+                self.calibrated_widths.remove(name);
+                self.pending_calibrations.retain(|_, (o, _, _)| o != name);
+            }
+            None => {
+                self.calibrated_widths.clear();
+                self.pending_calibrations.clear();
+            }
+        }
+    }
 
-            let state = self.query_full_state()?;
-            let windows_map: HashMap<u64, &Window> =
-                state.windows.iter().map(|w| (w.id, w)).collect();
+    /// Replaces the remembered workspace outputs and queues every workspace that moved to a
+    /// different output, since the output width decides what counts as maximized.
+    fn update_workspace_outputs(&mut self, outputs: HashMap<u64, String>) {
+        for (ws_id, output) in &outputs {
+            if let Some(old_output) = self.tracked_workspace_outputs.get(ws_id)
+                && old_output != output
+            {
+                info!(
+                    "workspace {} moved from {} to {}, re-evaluating",
+                    ws_id, old_output, output
+                );
+                self.pending_workspaces.insert(*ws_id);
+            }
+        }
+        self.tracked_workspace_outputs = outputs;
+    }
 
-            for ws_id in affected_workspaces {
-                if let Err(e) = self.evaluate_workspace(ws_id, &state, &windows_map) {
-                    error!("error evaluating workspace {}: {:?}", ws_id, e);
+    /// Replaces the remembered output widths and queues every workspace on an output that
+    /// appeared or changed width, e.g. after docking or a scale or mode change.
+    fn update_output_widths(&mut self, widths: HashMap<String, f64>) {
+        if !self.tracked_output_widths.is_empty() {
+            for (name, width) in &widths {
+                let old_width = self.tracked_output_widths.get(name);
+                if old_width == Some(width) {
+                    continue;
                 }
+                info!(
+                    "output {} changed logical width from {:?} to {}, re-evaluating its workspaces",
+                    name, old_width, width
+                );
+                self.invalidate_calibrations(Some(name));
+                let affected = self
+                    .tracked_workspace_outputs
+                    .iter()
+                    .filter(|(_, output)| *output == name)
+                    .map(|(&ws_id, _)| ws_id);
+                self.pending_workspaces.extend(affected);
             }
+        }
+        self.calibrated_widths
+            .retain(|name, _| widths.contains_key(name));
+        self.tracked_output_widths = widths;
+    }
 
-            for closed_pos in &closed_positions {
-                if let Some(closed_col) = closed_pos.column {
-                    let min_remaining_col = self
-                        .tracked_window_positions
-                        .values()
-                        .filter(|p| p.workspace_id == closed_pos.workspace_id)
-                        .filter_map(|p| p.column)
-                        .min();
-
-                    if let Some(min_col) = min_remaining_col {
-                        if closed_col > min_col {
-                            debug!(
-                                "closed window column {} had columns to the left, nudging viewport left",
-                                closed_col
-                            );
-                            let target_focus = self.query_focused_window().ok().flatten();
-                            let _ = self.send_action(Action::FocusColumnLeft {});
-                            if let Some(orig_id) = target_focus {
-                                let _ = self.send_action(Action::FocusWindow { id: orig_id });
-                            }
-                        }
-                    }
+    /// Cheaply checks whether any output changed size and evaluates the affected workspaces.
+    ///
+    /// niri has no output events, so the event loop calls this periodically while idle.
+    pub fn poll_outputs(&mut self) -> NiriResult<()> {
+        let widths = self.connection.query_output_widths()?;
+        if widths != self.tracked_output_widths {
+            self.output_check_requested = true;
+            return self.process_pending();
+        }
+        Ok(())
+    }
+
+    fn has_pending_work(&self) -> bool {
+        !self.pending_workspaces.is_empty() || self.output_check_requested
+    }
+
+    /// Evaluates every workspace touched by the events applied since the last call.
+    ///
+    /// While the overview is open the work is only collected, and evaluated in a single pass once
+    /// it closes.
+    ///
+    /// The full state is queried once, so workspaces that received several events in a row are
+    /// only evaluated against the latest layout.
+    pub fn process_pending(&mut self) -> NiriResult<()> {
+        if self.overview_open {
+            return Ok(());
+        }
+        if !self.has_pending_work() {
+            self.pending_closed_positions.clear();
+            return Ok(());
+        }
+
+        // Leave the work queued if the state can't be read, so the next pass picks it up again.
+        let state = self.query_full_state()?;
+        self.evaluate_pending(&state)
+    }
+
+    /// Brings the remembered layout up to date after (re)connecting to niri.
+    ///
+    /// The fresh window list is diffed against the tracked positions, so after a reconnect only
+    /// workspaces that changed while we were away get evaluated. On the first connection nothing
+    /// is tracked yet and every workspace is evaluated.
+    pub fn reconcile(&mut self) -> NiriResult<()> {
+        let state = self.query_full_state()?;
+
+        self.debounced_maximize_state
+            .retain(|id, _| state.windows.iter().any(|w| w.id == *id));
+        self.observed_maximized
+            .retain(|id, _| state.windows.iter().any(|w| w.id == *id));
+        self.widths_before_maximize
+            .retain(|id, _| state.windows.iter().any(|w| w.id == *id));
+        self.auto_tabbed_windows
+            .retain(|id| state.windows.iter().any(|w| w.id == *id));
+        self.column_tile_counts
+            .retain(|id, _| state.windows.iter().any(|w| w.id == *id));
+        self.column_proportions
+            .retain(|id, _| state.windows.iter().any(|w| w.id == *id));
+        self.overflow_stacked
+            .retain(|id| state.windows.iter().any(|w| w.id == *id));
+        self.apply_event(Event::WindowsChanged {
+            windows: state.windows.clone(),
+        });
+        info!(
+            "synchronized with niri: {} workspace(s) to re-evaluate",
+            self.pending_workspaces.len()
+        );
+
+        if self.overview_open {
+            return Ok(());
+        }
+        self.evaluate_pending(&state)
+    }
+
+    fn evaluate_pending(&mut self, state: &NiriState) -> NiriResult<()> {
+        self.output_check_requested = false;
+        self.update_workspace_outputs(state.ws_outputs.clone());
+        self.update_output_widths(state.output_widths.clone());
+
+        let mut affected_workspaces = std::mem::take(&mut self.pending_workspaces).into_iter();
+        let closed_positions = std::mem::take(&mut self.pending_closed_positions);
+
+        while let Some(ws_id) = affected_workspaces.next() {
+            if let Err(e) = self.evaluate_workspace(ws_id, state) {
+                if e.requires_reconnect() {
+                    // Keep the unfinished work around for the reconciliation after reconnecting.
+                    self.pending_workspaces.insert(ws_id);
+                    self.pending_workspaces.extend(affected_workspaces);
+                    self.pending_closed_positions = closed_positions;
+                    return Err(e);
+                }
+                error!("error evaluating workspace {}: {}", ws_id, e);
+            }
+        }
+        self.newly_opened.clear();
+
+        for closed_pos in &closed_positions {
+            if let Some(closed_col) = closed_pos.column {
+                let min_remaining_col = self
+                    .tracked_window_positions
+                    .values()
+                    .filter(|p| p.workspace_id == closed_pos.workspace_id)
+                    .filter_map(|p| p.column)
+                    .min();
+
+                if let Some(min_col) = min_remaining_col
+                    && closed_col > min_col
+                {
+                    debug!(
+                        "closed window column {} had columns to the left, adjusting viewport",
+                        closed_col
+                    );
+                    let target_focus = best_effort(self.query_focused_window())?.flatten();
+                    best_effort(self.apply_viewport_policy(closed_pos.workspace_id, target_focus))?;
                 }
             }
         }
@@ -361,3 +1203,16 @@ impl NiriContext {
         Ok(())
     }
 }
+
+/// Passes on errors that require a reconnect and drops all others, for requests whose failure
+/// would otherwise only cost some polish, like restoring focus.
+fn best_effort<T>(result: NiriResult<T>) -> NiriResult<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.requires_reconnect() => Err(e),
+        Err(e) => {
+            debug!("ignoring failed request: {}", e);
+            Ok(None)
+        }
+    }
+}
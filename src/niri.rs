@@ -0,0 +1,38 @@
+//! Adapter over the niri-ipc release selected with the `niri-*` cargo features.
+//!
+//! The rest of the crate imports niri types only from here. Supporting another niri release means
+//! adding a `niri-*` feature for its niri-ipc version and a matching `version` module below that
+//! exposes the same items, converting wire types where they differ from the ones used here.
+
+#[cfg(not(feature = "niri-25-8"))]
+compile_error!("enable one of the niri-* features to select the niri release to build against");
+
+#[cfg(feature = "niri-25-8")]
+mod version {
+    pub use niri_ipc::socket::SOCKET_PATH_ENV;
+    pub use niri_ipc::{
+        Action, ColumnDisplay, Event, Reply, Request, Response, SizeChange, Window,
+    };
+
+    /// niri release these types correspond to, as `(year, month)`.
+    pub const SUPPORTED_NIRI_VERSION: (u32, u32) = (25, 8);
+
+    /// Events added by later niri releases that niritiling has no use for, by release. The types
+    /// above can't decode them; everything else niritiling reads from those releases still
+    /// decodes, since new fields are ignored.
+    const NEWER_EVENTS: &[((u32, u32), &[&str])] = &[(
+        (25, 11),
+        &["WindowFocusTimestampChanged", "ScreenshotCaptured"],
+    )];
+
+    /// Events the `running` niri release may send that are known to be safe to skip.
+    pub fn newer_events(running: (u32, u32)) -> Vec<&'static str> {
+        NEWER_EVENTS
+            .iter()
+            .filter(|(release, _)| *release <= running)
+            .flat_map(|(_, events)| events.iter().copied())
+            .collect()
+    }
+}
+
+pub use version::*;
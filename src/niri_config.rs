@@ -0,0 +1,129 @@
+//! Read-only access to the `layout` section of niri's own `config.kdl`.
+//!
+//! Only the handful of settings that decide column widths are extracted; the file is parsed as
+//! KDL v1, like niri does, and everything else in it is ignored.
+
+use crate::config::{ColumnWidth, Struts, config_home, read_optional};
+use anyhow::{Context, Result};
+use kdl::{KdlDocument, KdlEntry, KdlError, KdlNode};
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// Environment variable niri reads its config path from.
+const CONFIG_PATH_ENV: &str = "NIRI_CONFIG";
+
+/// The parts of niri's `layout` section niritiling cares about. `None` means niri's config does
+/// not set the value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NiriLayout {
+    pub gaps: Option<f64>,
+    pub struts: Option<Struts>,
+    pub default_column_width: Option<ColumnWidth>,
+}
+
+/// Reads the layout settings from `path`, or from niri's default config location if `path` is
+/// `None`, returning the file that was read.
+pub fn load(path: Option<&Path>) -> Result<Option<(PathBuf, NiriLayout)>> {
+    let Some((path, contents)) = read_optional(path, default_path())? else {
+        return Ok(None);
+    };
+    let layout = parse_layout(&contents)
+        .with_context(|| format!("parsing niri config {}", path.display()))?;
+    info!(
+        "inherited layout settings from {}: {:?}",
+        path.display(),
+        layout
+    );
+    Ok(Some((path, layout)))
+}
+
+/// Extracts the layout settings from the contents of a niri config file.
+///
+/// Later `layout` sections override earlier ones, like in niri.
+pub fn parse_layout(contents: &str) -> Result<NiriLayout> {
+    let document: KdlDocument = contents.parse().map_err(|e: KdlError| {
+        let line = contents
+            .chars()
+            .take(e.span.offset())
+            .filter(|&c| c == '\n')
+            .count()
+            + 1;
+        anyhow::anyhow!("line {}: {}", line, e)
+    })?;
+    let mut layout = NiriLayout::default();
+
+    for section in document
+        .nodes()
+        .iter()
+        .filter(|n| n.name().value() == "layout")
+    {
+        for node in children(section) {
+            match node.name().value() {
+                "gaps" => layout.gaps = Some(number_arg(node)?),
+                "struts" => {
+                    let mut struts = Struts::default();
+                    for side in children(node) {
+                        let value = number_arg(side)?;
+                        match side.name().value() {
+                            "left" => struts.left = value,
+                            "right" => struts.right = value,
+                            "top" => struts.top = value,
+                            "bottom" => struts.bottom = value,
+                            _ => {}
+                        }
+                    }
+                    layout.struts = Some(struts);
+                }
+                "default-column-width" => {
+                    // An empty block lets windows pick their own width.
+                    layout.default_column_width = match children(node).first() {
+                        Some(width) => Some(column_width(width)?),
+                        None => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(layout)
+}
+
+/// niri's default config location: `$NIRI_CONFIG`, then `$XDG_CONFIG_HOME/niri/config.kdl`.
+fn default_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_PATH_ENV).filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    Some(config_home()?.join("niri").join("config.kdl"))
+}
+
+/// The first argument of `node` as a number.
+fn number_arg(node: &KdlNode) -> Result<f64> {
+    let value = node
+        .entries()
+        .iter()
+        .find(|entry| entry.name().is_none())
+        .map(KdlEntry::value);
+    value
+        .and_then(|v| v.as_f64().or_else(|| v.as_i64().map(|n| n as f64)))
+        .with_context(|| {
+            format!(
+                "`{}` expects a number, got {:?}",
+                node.name().value(),
+                value
+            )
+        })
+}
+
+fn column_width(node: &KdlNode) -> Result<ColumnWidth> {
+    match node.name().value() {
+        "proportion" => Ok(ColumnWidth::Proportion(number_arg(node)?)),
+        "fixed" => Ok(ColumnWidth::Fixed(number_arg(node)?)),
+        other => anyhow::bail!("unknown column width kind `{}`", other),
+    }
+}
+
+/// The child nodes of `node`, empty if it has no block.
+fn children(node: &KdlNode) -> &[KdlNode] {
+    node.children().map_or(&[], KdlDocument::nodes)
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Counters shared between the event loop and the control socket.
+#[derive(Debug, Default)]
+pub struct Stats {
+    connected: AtomicBool,
+    reconnects: AtomicU64,
+    ipc_timeouts: AtomicU64,
+    events: AtomicU64,
+}
+
+impl Stats {
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_ipc_timeout(&self) {
+        self.ipc_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_events(&self, count: u64) {
+        self.events.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> StatusReport {
+        StatusReport {
+            connected: self.connected.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            ipc_timeouts: self.ipc_timeouts.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of [`Stats`] as printed by `niritiling status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub connected: bool,
+    pub reconnects: u64,
+    pub ipc_timeouts: u64,
+    pub events: u64,
+}
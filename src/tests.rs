@@ -1,7 +1,17 @@
-use super::connection::{NiriConnection, NiriState, WindowPosition};
+use super::backoff::Backoff;
+use super::config::{
+    ColumnWidth, Config, Mode, OverflowTarget, Struts, ViewportPolicy, WorkspaceConfig,
+    read_optional,
+};
+use super::connection::{
+    EventStream, NiriConnection, NiriState, SocketConnection, WindowPosition, parse_niri_version,
+    socket_candidates,
+};
+use super::error::{NiriError, NiriResult};
 use super::manager::NiriContext;
-use anyhow::Result;
-use niri_ipc::{Action, Event, Window};
+use super::niri::{Action, ColumnDisplay, Event, SizeChange, Window, newer_events};
+use super::niri_config::{NiriLayout, parse_layout};
+use super::status::Stats;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
@@ -10,22 +20,61 @@ pub struct MockState {
     pub actions: Vec<Action>,
     pub focused_window: Option<u64>,
     pub state: NiriState,
+    pub state_queries: usize,
+    pub reject_actions: bool,
+    /// Refuse window-targeted `SetWindowWidth`, like a niri that can only resize the focused
+    /// column.
+    pub reject_targeted_resize: bool,
+    pub disconnected: bool,
 }
 
 pub struct MockConnection {
     pub shared: Arc<Mutex<MockState>>,
 }
 
+fn mock_disconnected() -> NiriError {
+    NiriError::transport("mock", std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+}
+
 impl NiriConnection for MockConnection {
-    fn send_action(&mut self, action: Action) -> Result<()> {
-        self.shared.lock().unwrap().actions.push(action);
+    fn send_action(&mut self, action: Action) -> NiriResult<()> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.disconnected {
+            return Err(mock_disconnected());
+        }
+        if shared.reject_actions
+            || (shared.reject_targeted_resize
+                && matches!(action, Action::SetWindowWidth { id: Some(_), .. }))
+        {
+            return Err(NiriError::Rejected {
+                action: Box::new(action),
+                message: "mock rejection".to_string(),
+            });
+        }
+        shared.actions.push(action);
         Ok(())
     }
-    fn query_focused_window(&mut self) -> Result<Option<u64>> {
-        Ok(self.shared.lock().unwrap().focused_window)
+    fn query_focused_window(&mut self) -> NiriResult<Option<u64>> {
+        let shared = self.shared.lock().unwrap();
+        if shared.disconnected {
+            return Err(mock_disconnected());
+        }
+        Ok(shared.focused_window)
+    }
+    fn query_output_widths(&mut self) -> NiriResult<HashMap<String, f64>> {
+        let shared = self.shared.lock().unwrap();
+        if shared.disconnected {
+            return Err(mock_disconnected());
+        }
+        Ok(shared.state.output_widths.clone())
     }
-    fn query_full_state(&mut self) -> Result<NiriState> {
-        Ok(self.shared.lock().unwrap().state.clone())
+    fn query_full_state(&mut self) -> NiriResult<NiriState> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.disconnected {
+            return Err(mock_disconnected());
+        }
+        shared.state_queries += 1;
+        Ok(shared.state.clone())
     }
 }
 
@@ -75,6 +124,52 @@ fn create_mock_fullscreen_window(id: u64, ws_id: u64, width: f64) -> Window {
     serde_json::from_value(v).expect("failed to deserialize mock window")
 }
 
+fn create_mock_workspaces_changed(workspaces: &[(u64, &str)]) -> Event {
+    use serde_json::json;
+    let workspaces: Vec<_> = workspaces
+        .iter()
+        .map(|&(id, output)| {
+            json!({
+                "id": id,
+                "idx": id,
+                "name": null,
+                "output": output,
+                "is_urgent": false,
+                "is_active": true,
+                "is_focused": false,
+                "active_window_id": null
+            })
+        })
+        .collect();
+    serde_json::from_value(json!({ "WorkspacesChanged": { "workspaces": workspaces } }))
+        .expect("failed to deserialize mock workspaces")
+}
+
+/// Whether `action` widens a column to the full output width.
+fn is_maximize(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::SetWindowWidth {
+            change: SizeChange::SetProportion(p),
+            ..
+        } if *p == 100.0
+    )
+}
+
+/// Proportions (in percent) set per window id, in order.
+fn proportions_set(actions: &[Action]) -> Vec<(u64, f64)> {
+    actions
+        .iter()
+        .filter_map(|a| match a {
+            Action::SetWindowWidth {
+                id: Some(id),
+                change: SizeChange::SetProportion(p),
+            } => Some((*id, *p)),
+            _ => None,
+        })
+        .collect()
+}
+
 fn setup_test(windows: Vec<Window>) -> (NiriContext, Arc<Mutex<MockState>>) {
     let output_name = "eDP-1".to_string();
     let mut output_widths = HashMap::new();
@@ -89,14 +184,21 @@ fn setup_test(windows: Vec<Window>) -> (NiriContext, Arc<Mutex<MockState>>) {
         state: NiriState {
             windows,
             output_widths,
+            output_heights: HashMap::new(),
             ws_outputs,
+            ws_names: HashMap::new(),
+            focused_workspace: Some(1),
         },
+        state_queries: 0,
+        reject_actions: false,
+        reject_targeted_resize: false,
+        disconnected: false,
     }));
 
     let conn = Box::new(MockConnection {
         shared: shared.clone(),
     });
-    (NiriContext::new(conn), shared)
+    (NiriContext::new(conn, Config::default()), shared)
 }
 
 #[test]
@@ -110,11 +212,7 @@ fn test_opened_on_empty_maximizes() {
         .unwrap();
 
     let actions = &shared.lock().unwrap().actions;
-    assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {}))
-    );
+    assert!(actions.iter().any(is_maximize));
 }
 
 #[test]
@@ -128,6 +226,7 @@ fn test_two_columns_unmaximize() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -141,8 +240,8 @@ fn test_two_columns_unmaximize() {
     assert!(
         actions
             .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {})),
-        "MaximizeColumn should be sent to un-maximize"
+            .any(|a| matches!(a, Action::SetWindowWidth { id: Some(100), .. })),
+        "the maximized column should be resized to un-maximize"
     );
     assert!(
         actions
@@ -164,6 +263,7 @@ fn test_close_one_of_two_columns_maximizes_remaining() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -172,6 +272,7 @@ fn test_close_one_of_two_columns_maximizes_remaining() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -179,11 +280,7 @@ fn test_close_one_of_two_columns_maximizes_remaining() {
     ctx.handle_event(Event::WindowClosed { id: 101 }).unwrap();
 
     let actions = &shared.lock().unwrap().actions;
-    assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {}))
-    );
+    assert!(actions.iter().any(is_maximize));
 }
 
 #[test]
@@ -199,6 +296,7 @@ fn test_close_second_to_last_on_three_columns_nudges_viewport() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -207,6 +305,7 @@ fn test_close_second_to_last_on_three_columns_nudges_viewport() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -215,6 +314,7 @@ fn test_close_second_to_last_on_three_columns_nudges_viewport() {
             workspace_id: 1,
             column: Some(2),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -242,6 +342,7 @@ fn test_drag_into_column_maximizes_if_one_left() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -250,6 +351,7 @@ fn test_drag_into_column_maximizes_if_one_left() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -261,11 +363,7 @@ fn test_drag_into_column_maximizes_if_one_left() {
         .unwrap();
 
     let actions = &shared.lock().unwrap().actions;
-    assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {}))
-    );
+    assert!(actions.iter().any(is_maximize));
 }
 
 #[test]
@@ -280,6 +378,7 @@ fn test_drag_out_of_column_nudges_and_unmaximizes() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -288,6 +387,7 @@ fn test_drag_out_of_column_nudges_and_unmaximizes() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(1),
+            fullscreen: false,
         },
     );
 
@@ -302,7 +402,7 @@ fn test_drag_out_of_column_nudges_and_unmaximizes() {
     assert!(
         actions
             .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {}))
+            .any(|a| matches!(a, Action::SetWindowWidth { .. }))
     );
     assert!(
         actions
@@ -352,10 +452,8 @@ fn test_repro_fullscreen_move_bug() {
 
     let actions = &shared.lock().unwrap().actions;
     assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {})),
-        "MaximizeColumn should have been called for win1 in WS 1"
+        actions.iter().any(is_maximize),
+        "win1 in WS 1 should have been maximized"
     );
 }
 
@@ -387,9 +485,7 @@ fn test_repro_fullscreen_started_fs_bug() {
     {
         let actions = &shared.lock().unwrap().actions;
         assert!(
-            actions
-                .iter()
-                .any(|a| matches!(a, Action::MaximizeColumn {})),
+            actions.iter().any(is_maximize),
             "WS 1 should have been re-evaluated after win2 moved away"
         );
     }
@@ -412,6 +508,7 @@ fn test_drag_left_into_right_column_maximizes() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -420,6 +517,7 @@ fn test_drag_left_into_right_column_maximizes() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -434,9 +532,7 @@ fn test_drag_left_into_right_column_maximizes() {
 
     let actions = &shared.lock().unwrap().actions;
     assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {})),
+        actions.iter().any(is_maximize),
         "Should maximize the single remaining column after dragging left into right"
     );
 }
@@ -453,6 +549,7 @@ fn test_drag_left_into_right_column_via_layout_change() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -461,6 +558,7 @@ fn test_drag_left_into_right_column_via_layout_change() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -475,9 +573,7 @@ fn test_drag_left_into_right_column_via_layout_change() {
 
     let actions = &shared.lock().unwrap().actions;
     assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {})),
+        actions.iter().any(is_maximize),
         "Should maximize via layout change even when position appears unchanged"
     );
 }
@@ -494,6 +590,7 @@ fn test_stale_state_does_not_send_focus_column_left() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -502,6 +599,7 @@ fn test_stale_state_does_not_send_focus_column_left() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -530,6 +628,7 @@ fn test_second_event_after_stale_state_maximizes() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -538,6 +637,7 @@ fn test_second_event_after_stale_state_maximizes() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -558,9 +658,7 @@ fn test_second_event_after_stale_state_maximizes() {
 
     let actions = &shared.lock().unwrap().actions;
     assert!(
-        actions
-            .iter()
-            .any(|a| matches!(a, Action::MaximizeColumn {})),
+        actions.iter().any(is_maximize),
         "Should maximize when second event sees settled 1-column state"
     );
 }
@@ -578,6 +676,7 @@ fn test_close_rightmost_of_three_columns_nudges_viewport_left() {
             workspace_id: 1,
             column: Some(0),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -586,6 +685,7 @@ fn test_close_rightmost_of_three_columns_nudges_viewport_left() {
             workspace_id: 1,
             column: Some(1),
             tile: Some(0),
+            fullscreen: false,
         },
     );
     ctx.tracked_window_positions.insert(
@@ -594,6 +694,7 @@ fn test_close_rightmost_of_three_columns_nudges_viewport_left() {
             workspace_id: 1,
             column: Some(2),
             tile: Some(0),
+            fullscreen: false,
         },
     );
 
@@ -608,3 +709,1351 @@ fn test_close_rightmost_of_three_columns_nudges_viewport_left() {
         "FocusColumnLeft should be sent to nudge viewport left after closing rightmost column"
     );
 }
+
+#[test]
+fn test_burst_of_events_is_evaluated_once() {
+    let (mut ctx, shared) = setup_test(Vec::new());
+
+    let win1 = create_mock_window(100, 1, 0, 0, 500.0);
+    let win1_resized = create_mock_window(100, 1, 0, 0, 600.0);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .windows
+        .push(win1_resized.clone());
+
+    ctx.apply_event(Event::WindowOpenedOrChanged { window: win1 });
+    ctx.apply_event(Event::WindowOpenedOrChanged {
+        window: win1_resized.clone(),
+    });
+    ctx.apply_event(Event::WindowLayoutsChanged {
+        changes: vec![(100, win1_resized.layout)],
+    });
+    ctx.process_pending().unwrap();
+
+    let shared = shared.lock().unwrap();
+    assert_eq!(
+        shared.state_queries, 1,
+        "a burst of events should share a single state query"
+    );
+    assert_eq!(
+        shared.actions.iter().filter(|a| is_maximize(a)).count(),
+        1,
+        "the workspace should only be evaluated once for the whole burst"
+    );
+}
+
+#[test]
+fn test_config_defaults_and_unknown_keys() {
+    let config: Config = serde_json::from_str("{}").unwrap();
+    assert_eq!(
+        config.coalesce_window_ms,
+        Config::default().coalesce_window_ms
+    );
+
+    let config: Config = serde_json::from_str(r#"{"coalesce_window_ms": 75}"#).unwrap();
+    assert_eq!(config.coalesce_window().as_millis(), 75);
+
+    assert!(
+        serde_json::from_str::<Config>(r#"{"coalesce_window": 75}"#).is_err(),
+        "misspelled keys should be rejected rather than silently ignored"
+    );
+}
+
+#[test]
+fn test_rejected_action_does_not_require_reconnect() {
+    let win = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win.clone()]);
+    shared.lock().unwrap().reject_actions = true;
+
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win })
+        .expect("a rejected action should be logged, not propagated");
+}
+
+#[test]
+fn test_transport_failure_requires_reconnect() {
+    let win = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win.clone()]);
+    shared.lock().unwrap().disconnected = true;
+
+    let err = ctx
+        .handle_event(Event::WindowOpenedOrChanged { window: win })
+        .expect_err("a broken socket should be propagated");
+    assert!(err.requires_reconnect());
+}
+
+#[test]
+fn test_backoff_grows_with_jitter_and_caps() {
+    use std::time::Duration;
+
+    let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(800));
+    let expected_steps = [100, 200, 400, 800, 800, 800];
+    for step in expected_steps {
+        let delay = backoff.next_delay();
+        let step = Duration::from_millis(step);
+        assert!(
+            delay >= step / 2 && delay <= step,
+            "delay {:?} outside of [{:?}, {:?}]",
+            delay,
+            step / 2,
+            step
+        );
+    }
+
+    backoff.reset();
+    assert!(backoff.next_delay() <= Duration::from_millis(100));
+}
+
+#[test]
+fn test_socket_candidates_only_lists_niri_sockets_newest_first() {
+    let dir = std::env::temp_dir().join(format!("niritiling-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let old = dir.join("niri.wayland-1.100.sock");
+    let new = dir.join("niri.wayland-1.200.sock");
+    std::fs::write(&old, "").unwrap();
+    std::fs::write(dir.join("wayland-1"), "").unwrap();
+    std::fs::write(dir.join("niri.log"), "").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(&new, "").unwrap();
+
+    let candidates = socket_candidates(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(candidates, vec![new, old]);
+}
+
+#[test]
+fn test_reconcile_after_reconnect_only_touches_changed_workspaces() {
+    let ws1_win = create_mock_window(100, 1, 0, 0, 500.0);
+    let ws1_other = create_mock_window(101, 1, 1, 0, 500.0);
+    let ws2_win = create_mock_window(200, 2, 0, 0, 500.0);
+    let ws2_closed = create_mock_window(201, 2, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![
+        ws1_win.clone(),
+        ws1_other.clone(),
+        ws2_win.clone(),
+        ws2_closed.clone(),
+    ]);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .ws_outputs
+        .insert(2, "eDP-1".to_string());
+
+    ctx.reconcile().unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    // While disconnected, the second window on workspace 2 was closed.
+    let fresh = Arc::new(Mutex::new(MockState {
+        state: shared.lock().unwrap().state.clone(),
+        ..Default::default()
+    }));
+    fresh.lock().unwrap().state.windows.retain(|w| w.id != 201);
+    ctx.replace_connection(Box::new(MockConnection {
+        shared: fresh.clone(),
+    }));
+    ctx.reconcile().unwrap();
+
+    let actions = &fresh.lock().unwrap().actions;
+    assert!(
+        actions
+            .iter()
+            .any(|a| matches!(a, Action::SetWindowWidth { id: Some(200), .. })),
+        "the remaining window on workspace 2 should be targeted"
+    );
+    assert!(
+        !actions.iter().any(|a| matches!(
+            a,
+            Action::SetWindowWidth {
+                id: Some(100 | 101),
+                ..
+            } | Action::FocusWindow { id: 100 | 101 }
+        )),
+        "workspace 1 did not change and should be left alone"
+    );
+}
+
+#[test]
+fn test_work_interrupted_by_disconnect_is_retried_after_reconnect() {
+    let win = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win.clone()]);
+    ctx.tracked_window_positions.insert(
+        100,
+        WindowPosition {
+            workspace_id: 1,
+            column: Some(0),
+            tile: Some(0),
+            fullscreen: false,
+        },
+    );
+
+    shared.lock().unwrap().disconnected = true;
+    assert!(
+        ctx.handle_event(Event::WindowOpenedOrChanged {
+            window: win.clone()
+        })
+        .is_err()
+    );
+
+    shared.lock().unwrap().disconnected = false;
+    ctx.replace_connection(Box::new(MockConnection {
+        shared: shared.clone(),
+    }));
+    ctx.reconcile().unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        actions.iter().any(is_maximize),
+        "the workspace queued before the disconnect should still be evaluated"
+    );
+}
+
+#[test]
+fn test_stalled_niri_surfaces_timeout() {
+    let path = std::env::temp_dir().join(format!("niritiling-stall-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+    let stats = Arc::new(Stats::default());
+    let timeout = std::time::Duration::from_millis(50);
+    let mut conn = SocketConnection::connect(&path, timeout, stats.clone()).unwrap();
+    // Accept but only answer after the timeout, like a hung compositor.
+    let (mut peer, _) = listener.accept().unwrap();
+    let err = conn
+        .query_focused_window()
+        .expect_err("a stalled niri should not block forever");
+    assert!(matches!(err, NiriError::Timeout { .. }), "got {:?}", err);
+    assert!(err.requires_reconnect());
+    assert_eq!(stats.report().ipc_timeouts, 1);
+
+    // The late reply must not be taken for the answer to the next request.
+    std::io::Write::write_all(&mut peer, b"{\"Ok\":{\"FocusedWindow\":null}}\n").unwrap();
+    let err = conn
+        .send_action(Action::CenterColumn {})
+        .expect_err("the connection is out of sync after a timeout");
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(err, NiriError::Timeout { .. }), "got {:?}", err);
+    assert_eq!(
+        stats.report().ipc_timeouts,
+        1,
+        "counted once, where it happened"
+    );
+}
+
+#[test]
+fn test_parse_niri_version() {
+    assert_eq!(parse_niri_version("25.08 (8ed0da4)"), Some((25, 8)));
+    assert_eq!(parse_niri_version("25.11"), Some((25, 11)));
+    assert_eq!(
+        parse_niri_version("25.05.1 (unknown commit)"),
+        Some((25, 5))
+    );
+    assert_eq!(parse_niri_version("unknown"), None);
+}
+
+#[test]
+fn test_event_stream_skips_undecodable_events() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (ours, mut niri) = std::os::unix::net::UnixStream::pair().unwrap();
+    let handshake = std::thread::spawn(move || {
+        let mut request = String::new();
+        BufReader::new(&niri).read_line(&mut request).unwrap();
+        assert_eq!(request.trim(), r#""EventStream""#);
+        niri.write_all(b"{\"Ok\":\"Handled\"}\n").unwrap();
+        niri.write_all(b"{\"SomeFutureEvent\":{\"foo\":1}}\n")
+            .unwrap();
+        niri.write_all(b"{\"WindowClosed\":{\"id\":7}}\n").unwrap();
+        niri
+    });
+
+    let mut stream = EventStream::start(ours).unwrap();
+    let event = stream.next_event().unwrap();
+    assert!(matches!(event, Event::WindowClosed { id: 7 }));
+
+    drop(handshake.join().unwrap());
+    let err = stream
+        .next_event()
+        .expect_err("a closed stream is an error");
+    assert!(err.requires_reconnect());
+}
+
+#[test]
+fn test_niri_25_11_additions_are_ignored() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let window: Window = serde_json::from_value(serde_json::json!({
+        "id": 7,
+        "title": null,
+        "app_id": null,
+        "pid": null,
+        "workspace_id": 1,
+        "is_focused": true,
+        "is_floating": false,
+        "is_urgent": false,
+        "layout": {
+            "pos_in_scrolling_layout": [1, 1],
+            "tile_size": [500.0, 400.0],
+            "window_size": [500, 400],
+            "tile_pos_in_workspace_view": null,
+            "window_offset_in_tile": [0.0, 0.0]
+        },
+        "focus_timestamp": {"secs": 12, "nanos": 0}
+    }))
+    .expect("windows from niri 25.11 should decode");
+    assert_eq!(window.id, 7);
+
+    let (ours, mut niri) = std::os::unix::net::UnixStream::pair().unwrap();
+    let handshake = std::thread::spawn(move || {
+        let mut request = String::new();
+        BufReader::new(&niri).read_line(&mut request).unwrap();
+        niri.write_all(b"{\"Ok\":\"Handled\"}\n").unwrap();
+        niri.write_all(b"{\"WindowFocusTimestampChanged\":{\"id\":7,\"focus_timestamp\":null}}\n")
+            .unwrap();
+        niri.write_all(b"{\"WindowClosed\":{\"id\":7}}\n").unwrap();
+        niri
+    });
+    let mut stream = EventStream::start(ours).unwrap();
+    stream.set_niri_version((25, 11));
+    let event = stream.next_event().unwrap();
+    assert!(matches!(event, Event::WindowClosed { id: 7 }));
+    drop(handshake.join().unwrap());
+
+    assert!(newer_events((25, 8)).is_empty());
+    assert!(newer_events((26, 1)).contains(&"WindowFocusTimestampChanged"));
+}
+
+fn setup_two_outputs(windows: Vec<Window>) -> (NiriContext, Arc<Mutex<MockState>>) {
+    let (mut ctx, shared) = setup_test(windows);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .output_widths
+        .insert("DP-1".to_string(), 2000.0);
+    ctx.reconcile().unwrap();
+    shared.lock().unwrap().actions.clear();
+    (ctx, shared)
+}
+
+#[test]
+fn test_workspace_moved_to_wider_output_is_remaximized() {
+    let win = create_mock_window(100, 1, 0, 0, 950.0);
+    let (mut ctx, shared) = setup_two_outputs(vec![win]);
+    assert_eq!(
+        ctx.tracked_workspace_outputs.get(&1).map(String::as_str),
+        Some("eDP-1")
+    );
+
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .ws_outputs
+        .insert(1, "DP-1".to_string());
+    ctx.handle_event(create_mock_workspaces_changed(&[(1, "DP-1")]))
+        .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        actions.iter().any(is_maximize),
+        "a 950px column is no longer maximized on a 2000px output"
+    );
+}
+
+#[test]
+fn test_workspace_activated_checks_for_output_changes() {
+    let win = create_mock_window(100, 1, 0, 0, 950.0);
+    let (mut ctx, shared) = setup_two_outputs(vec![win]);
+
+    ctx.handle_event(Event::WorkspaceActivated {
+        id: 1,
+        focused: true,
+    })
+    .unwrap();
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "activating a workspace on the same output should not act"
+    );
+
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .ws_outputs
+        .insert(1, "DP-1".to_string());
+    ctx.handle_event(Event::WorkspaceActivated {
+        id: 1,
+        focused: true,
+    })
+    .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        actions.iter().any(is_maximize),
+        "the workspace changed outputs and should be re-evaluated"
+    );
+}
+
+#[test]
+fn test_output_scale_change_is_picked_up_by_polling() {
+    let win = create_mock_window(100, 1, 0, 0, 950.0);
+    let (mut ctx, shared) = setup_two_outputs(vec![win]);
+
+    ctx.poll_outputs().unwrap();
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "nothing changed, so polling should not act"
+    );
+    let queries_before = shared.lock().unwrap().state_queries;
+
+    // Lowering the scale of eDP-1 doubles its logical width.
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .output_widths
+        .insert("eDP-1".to_string(), 2000.0);
+    ctx.poll_outputs().unwrap();
+
+    let shared = shared.lock().unwrap();
+    assert_eq!(shared.state_queries, queries_before + 1);
+    assert!(
+        shared.actions.iter().any(is_maximize),
+        "workspaces on the resized output should be re-evaluated"
+    );
+}
+
+#[test]
+fn test_entering_fullscreen_keeps_column_and_does_not_touch_neighbours() {
+    let win1 = create_mock_window(100, 1, 0, 0, 500.0);
+    let win2 = create_mock_window(101, 1, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win1.clone(), win2.clone()]);
+    ctx.handle_event(Event::WindowsChanged {
+        windows: vec![win1, win2],
+    })
+    .unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    let win2_fs = create_mock_fullscreen_window(101, 1, 1000.0);
+    shared.lock().unwrap().state.windows.retain(|w| w.id != 101);
+    shared.lock().unwrap().state.windows.push(win2_fs.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win2_fs })
+        .unwrap();
+
+    assert_eq!(
+        ctx.tracked_window_positions.get(&101),
+        Some(&WindowPosition {
+            workspace_id: 1,
+            column: Some(1),
+            tile: Some(0),
+            fullscreen: true,
+        })
+    );
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "the fullscreen window still owns its column, so its neighbour must not be maximized"
+    );
+}
+
+#[test]
+fn test_leaving_fullscreen_restores_maximized_state() {
+    let win_fs = create_mock_fullscreen_window(100, 1, 1000.0);
+    let (mut ctx, shared) = setup_test(vec![win_fs.clone()]);
+    ctx.tracked_window_positions.insert(
+        100,
+        WindowPosition {
+            workspace_id: 1,
+            column: Some(0),
+            tile: Some(0),
+            fullscreen: true,
+        },
+    );
+
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win_fs })
+        .unwrap();
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "a fullscreen window is never resized"
+    );
+
+    let win_tiled = create_mock_window(100, 1, 0, 0, 500.0);
+    shared.lock().unwrap().state.windows = vec![win_tiled.clone()];
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win_tiled })
+        .unwrap();
+
+    assert!(!ctx.tracked_window_positions[&100].fullscreen);
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        actions.iter().any(is_maximize),
+        "the only column should be maximized again after leaving fullscreen"
+    );
+}
+
+#[test]
+fn test_overview_defers_evaluation_until_closed() {
+    let win1 = create_mock_window(100, 1, 0, 0, 500.0);
+    let win2 = create_mock_window(101, 1, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win1.clone(), win2.clone()]);
+    ctx.handle_event(Event::WindowsChanged {
+        windows: vec![win1, win2],
+    })
+    .unwrap();
+    let queries_before = shared.lock().unwrap().state_queries;
+
+    ctx.handle_event(Event::OverviewOpenedOrClosed { is_open: true })
+        .unwrap();
+
+    // Drag the second window into the first column and back out and in again.
+    for col in [0, 1, 0] {
+        let win2_moved = create_mock_window(101, 1, col, col ^ 1, 500.0);
+        shared.lock().unwrap().state.windows.retain(|w| w.id != 101);
+        shared
+            .lock()
+            .unwrap()
+            .state
+            .windows
+            .push(win2_moved.clone());
+        ctx.handle_event(Event::WindowLayoutsChanged {
+            changes: vec![(101, win2_moved.layout)],
+        })
+        .unwrap();
+    }
+    {
+        let shared = shared.lock().unwrap();
+        assert!(
+            shared.actions.is_empty(),
+            "no actions while the overview is open"
+        );
+        assert_eq!(shared.state_queries, queries_before);
+    }
+
+    ctx.handle_event(Event::OverviewOpenedOrClosed { is_open: false })
+        .unwrap();
+
+    let shared = shared.lock().unwrap();
+    assert_eq!(
+        shared.state_queries,
+        queries_before + 1,
+        "closing the overview should evaluate everything in one pass"
+    );
+    assert!(
+        shared.actions.iter().any(is_maximize),
+        "the single remaining column should be maximized once the overview closes"
+    );
+}
+
+#[test]
+fn test_maximized_detection_accounts_for_gaps_and_struts() {
+    // With 60px gaps and a 40px strut, niri maximizes a column on a 1000px output to 840px.
+    let win = create_mock_window(100, 1, 0, 0, 840.0);
+    let (mut ctx, shared) = setup_test(vec![win.clone()]);
+    ctx.layout.gaps = Some(60.0);
+    ctx.layout.struts = Some(Struts {
+        left: 40.0,
+        ..Struts::default()
+    });
+
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win })
+        .unwrap();
+
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "an already maximized column must not be toggled back"
+    );
+}
+
+#[test]
+fn test_zero_ipc_timeout_is_rejected() {
+    assert!(Config::default().validate().is_ok());
+
+    let config: Config = serde_json::from_str(r#"{"ipc_timeout_ms": 0}"#).unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_only_an_explicit_config_path_must_exist() {
+    let missing = std::env::temp_dir().join(format!("niritiling-missing-{}", std::process::id()));
+    assert!(
+        read_optional(None, Some(missing.clone()))
+            .unwrap()
+            .is_none()
+    );
+    assert!(read_optional(None, None).unwrap().is_none());
+    assert!(read_optional(Some(&missing), None).is_err());
+}
+
+#[test]
+fn test_maximized_ratio_hysteresis() {
+    let layout = Config::default().layout;
+
+    assert!(layout.is_maximized_ratio(0.99, None));
+    assert!(!layout.is_maximized_ratio(0.5, Some(true)));
+
+    // Between the thresholds the previous decision stands.
+    assert!(layout.is_maximized_ratio(0.92, Some(true)));
+    assert!(!layout.is_maximized_ratio(0.92, Some(false)));
+    assert!(!layout.is_maximized_ratio(0.92, None));
+
+    let mut config = Config::default();
+    config.layout.unmaximized_ratio = 0.97;
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_maximized_width_is_calibrated_per_output() {
+    let win = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win.clone()]);
+
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win })
+        .unwrap();
+    assert!(shared.lock().unwrap().actions.iter().any(is_maximize));
+
+    // niri maximizes to 900px here, narrower than the configured layout predicts.
+    let maximized = create_mock_window(100, 1, 0, 0, 900.0);
+    shared.lock().unwrap().state.windows = vec![maximized.clone()];
+    shared.lock().unwrap().actions.clear();
+    ctx.handle_event(Event::WindowLayoutsChanged {
+        changes: vec![(100, maximized.layout.clone())],
+    })
+    .unwrap();
+
+    assert_eq!(ctx.calibrated_widths.get("eDP-1"), Some(&900.0));
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "the calibrated width must count as maximized"
+    );
+
+    // A different output width invalidates the calibration.
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .output_widths
+        .insert("eDP-1".to_string(), 1200.0);
+    ctx.poll_outputs().unwrap();
+    assert!(!ctx.calibrated_widths.contains_key("eDP-1"));
+}
+
+#[test]
+fn test_implausible_maximized_width_is_not_calibrated() {
+    let win = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win.clone()]);
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win })
+        .unwrap();
+    assert!(shared.lock().unwrap().actions.iter().any(is_maximize));
+
+    // The window has a maximum width and stops at 600px instead of about 968px.
+    let capped = create_mock_window(100, 1, 0, 0, 600.0);
+    shared.lock().unwrap().state.windows = vec![capped.clone()];
+    ctx.handle_event(Event::WindowLayoutsChanged {
+        changes: vec![(100, capped.layout.clone())],
+    })
+    .unwrap();
+    assert!(ctx.calibrated_widths.is_empty());
+
+    // Later columns are still judged by the ratio.
+    let maximized = create_mock_window(100, 1, 0, 0, 968.0);
+    shared.lock().unwrap().state.windows = vec![maximized.clone()];
+    shared.lock().unwrap().actions.clear();
+    ctx.handle_event(Event::WindowLayoutsChanged {
+        changes: vec![(100, maximized.layout.clone())],
+    })
+    .unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+}
+
+#[test]
+fn test_parse_niri_layout() {
+    let layout = parse_layout(
+        r##"
+        // A typical niri config, trimmed down.
+        input {
+            keyboard { xkb { layout "us,de"; options "grp:win_space_toggle"; }; }
+        }
+        output "eDP-1" { scale 1.5; }
+
+        layout {
+            gaps 8
+            /- gaps 100
+            center-focused-column "never"
+            preset-column-widths {
+                proportion 0.33333
+                proportion 0.5
+            }
+            default-column-width { proportion 0.5; }
+            /* struts { left 1000; } */
+            struts {
+                left 32
+                right 32.5
+            }
+            focus-ring { width 4; active-color "#7fc8ff"; }
+        }
+
+        window-rule {
+            match app-id=r#"^org\.wezfurlong\.wezterm$"#
+            default-column-width {}
+        }
+        binds {
+            Mod+T hotkey-overlay-title="Open a Terminal" { spawn "alacritty"; }
+        }
+        "##,
+    )
+    .unwrap();
+
+    assert_eq!(
+        layout,
+        NiriLayout {
+            gaps: Some(8.0),
+            struts: Some(Struts {
+                left: 32.0,
+                right: 32.5,
+                ..Struts::default()
+            }),
+            default_column_width: Some(ColumnWidth::Proportion(0.5)),
+        }
+    );
+
+    assert_eq!(parse_layout("layout {}").unwrap(), NiriLayout::default());
+    assert!(parse_layout("layout { gaps 8").is_err());
+}
+
+#[test]
+fn test_niri_layout_fills_in_unset_settings() {
+    let mut config = Config::default();
+    config.layout.gaps = Some(4.0);
+    let mut layout = config.layout.clone();
+    layout.inherit(&NiriLayout {
+        gaps: Some(8.0),
+        struts: Some(Struts {
+            left: 50.0,
+            ..Struts::default()
+        }),
+        default_column_width: Some(ColumnWidth::Fixed(800.0)),
+    });
+
+    assert_eq!(layout.gaps, Some(4.0), "niritiling's own settings win");
+    assert_eq!(layout.maximized_width(1000.0), 1000.0 - 50.0 - 8.0);
+    assert_eq!(layout.default_column_width, Some(ColumnWidth::Fixed(800.0)));
+}
+
+#[test]
+fn test_niri_config_is_reread_after_a_failed_load() {
+    let path = std::env::temp_dir().join(format!("niritiling-niri-{}.kdl", std::process::id()));
+    std::fs::write(&path, "layout { gaps 8").unwrap();
+    let (mut ctx, _shared) = setup_test(vec![]);
+    ctx.config.niri_config = Some(path.clone());
+    ctx.load_niri_config();
+    assert_eq!(ctx.layout.gaps, None);
+
+    std::fs::write(&path, "layout { gaps 8; }").unwrap();
+    ctx.handle_event(Event::ConfigLoaded { failed: false })
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(ctx.layout.gaps, Some(8.0));
+}
+
+#[test]
+fn test_unmaximize_restores_default_column_width() {
+    let win1 = create_mock_window(100, 1, 0, 0, 968.0);
+    let (mut ctx, shared) = setup_test(vec![win1]);
+    ctx.layout.default_column_width = Some(ColumnWidth::Proportion(0.5));
+
+    let win2 = create_mock_window(101, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows.push(win2.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win2 })
+        .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(actions.iter().any(|a| matches!(
+        a,
+        Action::SetWindowWidth {
+            id: Some(100),
+            change: SizeChange::SetProportion(p),
+        } if *p == 50.0
+    )));
+    assert!(!actions.iter().any(is_maximize));
+}
+
+#[test]
+fn test_viewport_policy_applies_after_unmaximize() {
+    let win1 = create_mock_window(100, 1, 0, 0, 968.0);
+    let (mut ctx, shared) = setup_test(vec![win1]);
+    ctx.config.viewport = ViewportPolicy::CenterFocused;
+    shared.lock().unwrap().focused_window = Some(101);
+
+    let win2 = create_mock_window(101, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows.push(win2.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win2 })
+        .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        !actions
+            .iter()
+            .any(|a| matches!(a, Action::FocusColumnLeft {}))
+    );
+    assert!(matches!(
+        actions.as_slice(),
+        [.., Action::FocusWindow { id: 101 }, Action::CenterColumn {}]
+    ));
+}
+
+#[test]
+fn test_keep_viewport_policy_leaves_viewport_after_close() {
+    let win1 = create_mock_window(100, 1, 0, 0, 500.0);
+    let win2 = create_mock_window(101, 1, 1, 0, 500.0);
+    let win3 = create_mock_window(102, 1, 2, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win1, win2, win3]);
+    ctx.config.viewport = ViewportPolicy::Keep;
+    ctx.reconcile().unwrap();
+    shared.lock().unwrap().focused_window = Some(101);
+
+    shared.lock().unwrap().state.windows.retain(|w| w.id != 102);
+    ctx.handle_event(Event::WindowClosed { id: 102 }).unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        !actions.iter().any(|a| matches!(
+            a,
+            Action::FocusColumnLeft {}
+                | Action::FocusColumnFirst {}
+                | Action::FocusWindow { .. }
+                | Action::CenterColumn {}
+                | Action::CenterVisibleColumns {}
+        )),
+        "unexpected viewport actions: {:?}",
+        actions
+    );
+}
+
+#[test]
+fn test_maximize_does_not_move_focus() {
+    let win1 = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win1.clone()]);
+    shared.lock().unwrap().focused_window = Some(200);
+
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win1 })
+        .unwrap();
+
+    let actions = shared.lock().unwrap().actions.clone();
+    assert!(matches!(
+        actions.as_slice(),
+        [Action::SetWindowWidth { id: Some(100), .. }]
+    ));
+    assert!(is_maximize(&actions[0]));
+
+    // Splitting the workspace again restores the width from before maximizing, still without
+    // touching focus. The remembered width wins over the default column width.
+    shared.lock().unwrap().actions.clear();
+    ctx.config.viewport = ViewportPolicy::Keep;
+    ctx.layout.default_column_width = Some(ColumnWidth::Proportion(0.5));
+    shared.lock().unwrap().focused_window = None;
+    let maximized = create_mock_window(100, 1, 0, 0, 968.0);
+    let win2 = create_mock_window(101, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows = vec![maximized, win2.clone()];
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win2 })
+        .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::SetWindowWidth {
+                id: Some(100),
+                change: SizeChange::SetFixed(500),
+            }]
+        ),
+        "unexpected actions: {:?}",
+        actions
+    );
+}
+
+#[test]
+fn test_refused_targeted_resize_falls_back_to_focus() {
+    let win1 = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![win1.clone()]);
+    {
+        let mut shared = shared.lock().unwrap();
+        shared.focused_window = Some(200);
+        shared.reject_targeted_resize = true;
+    }
+
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: win1 })
+        .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(matches!(
+        actions.as_slice(),
+        [
+            Action::FocusWindow { id: 100 },
+            Action::SetColumnWidth {
+                change: SizeChange::SetProportion(_)
+            },
+            Action::FocusWindow { id: 200 },
+        ]
+    ));
+}
+
+#[test]
+fn test_multi_tile_column_is_treated_as_a_unit() {
+    // Listed bottom tile first; the top tile can't grow past 600px.
+    let bottom = create_mock_window(101, 1, 0, 1, 500.0);
+    let top = create_mock_window(100, 1, 0, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![bottom, top]);
+
+    ctx.reconcile().unwrap();
+    let actions = shared.lock().unwrap().actions.clone();
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::SetWindowWidth { id: Some(100), .. }]
+        ),
+        "the top tile should represent the column: {:?}",
+        actions
+    );
+
+    // Once maximized, the column counts as maximized even though one tile stays narrower.
+    shared.lock().unwrap().actions.clear();
+    let bottom = create_mock_window(101, 1, 0, 1, 968.0);
+    let top = create_mock_window(100, 1, 0, 0, 600.0);
+    shared.lock().unwrap().state.windows = vec![bottom.clone(), top];
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: bottom })
+        .unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    // A second column un-maximizes the stacked one through its top tile.
+    let right = create_mock_window(102, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows.push(right.clone());
+    ctx.debounced_maximize_state.clear();
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: right })
+        .unwrap();
+    let actions = &shared.lock().unwrap().actions;
+    let resized: Vec<_> = actions
+        .iter()
+        .filter_map(|a| match a {
+            Action::SetWindowWidth { id, .. } => *id,
+            _ => None,
+        })
+        .collect();
+    assert_eq!(resized, vec![100]);
+}
+
+#[test]
+fn test_crowded_column_is_tabbed_and_restored() {
+    let left = create_mock_window(100, 1, 0, 0, 500.0);
+    let stacked: Vec<Window> = (0..3)
+        .map(|tile| create_mock_window(101 + tile as u64, 1, 1, tile, 500.0))
+        .collect();
+    let mut windows = vec![left];
+    windows.extend(stacked);
+    let (mut ctx, shared) = setup_test(windows);
+    ctx.config.auto_tab_threshold = 2;
+    shared.lock().unwrap().focused_window = Some(100);
+
+    ctx.reconcile().unwrap();
+    let actions = shared.lock().unwrap().actions.clone();
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [
+                Action::FocusWindow { id: 101 },
+                Action::SetColumnDisplay {
+                    display: ColumnDisplay::Tabbed
+                },
+                Action::FocusWindow { id: 100 },
+            ]
+        ),
+        "unexpected actions: {:?}",
+        actions
+    );
+
+    // Still crowded: nothing to do.
+    shared.lock().unwrap().actions.clear();
+    let moved = create_mock_window(100, 1, 0, 0, 500.0);
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: moved })
+        .unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    // One tile closed: back to normal.
+    shared.lock().unwrap().state.windows.retain(|w| w.id != 103);
+    ctx.handle_event(Event::WindowClosed { id: 103 }).unwrap();
+    let actions = &shared.lock().unwrap().actions;
+    assert!(actions.iter().any(|a| matches!(
+        a,
+        Action::SetColumnDisplay {
+            display: ColumnDisplay::Normal
+        }
+    )));
+}
+
+#[test]
+fn test_tile_heights_are_equalized_when_a_column_changes() {
+    let top = create_mock_window(100, 1, 0, 0, 500.0);
+    let bottom = create_mock_window(101, 1, 0, 1, 500.0);
+    let right = create_mock_window(102, 1, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![top, bottom, right]);
+    ctx.config.equalize_tile_heights = true;
+    ctx.reconcile().unwrap();
+    assert!(
+        shared.lock().unwrap().actions.is_empty(),
+        "existing columns are left alone"
+    );
+
+    // The right window is consumed into the first column as a third tile.
+    let consumed = create_mock_window(102, 1, 0, 2, 500.0);
+    shared.lock().unwrap().state.windows.retain(|w| w.id != 102);
+    shared.lock().unwrap().state.windows.push(consumed.clone());
+    ctx.handle_event(Event::WindowLayoutsChanged {
+        changes: vec![(102, consumed.layout.clone())],
+    })
+    .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    let mut reset: Vec<u64> = actions
+        .iter()
+        .filter_map(|a| match a {
+            Action::ResetWindowHeight { id } => *id,
+            _ => None,
+        })
+        .collect();
+    reset.sort_unstable();
+    assert_eq!(reset, vec![100, 101, 102]);
+}
+
+#[test]
+fn test_max_columns_stacks_new_windows_and_rebalances() {
+    let left = create_mock_window(100, 1, 0, 0, 500.0);
+    let right = create_mock_window(101, 1, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![left, right]);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .ws_names
+        .insert(1, "code".to_string());
+    ctx.config.workspaces.insert(
+        "code".to_string(),
+        WorkspaceConfig {
+            max_columns: Some(2),
+            ..WorkspaceConfig::default()
+        },
+    );
+    ctx.reconcile().unwrap();
+
+    // A third window opens between the two columns.
+    let mut windows = vec![
+        create_mock_window(100, 1, 0, 0, 500.0),
+        create_mock_window(102, 1, 1, 0, 500.0),
+        create_mock_window(101, 1, 2, 0, 500.0),
+    ];
+    shared.lock().unwrap().state.windows = windows.clone();
+    ctx.handle_event(Event::WindowOpenedOrChanged {
+        window: windows[1].clone(),
+    })
+    .unwrap();
+    let actions = shared.lock().unwrap().actions.clone();
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::ConsumeOrExpelWindowLeft { id: Some(102) }]
+        ),
+        "unexpected actions: {:?}",
+        actions
+    );
+
+    // Stacked into the first column; nothing else to do.
+    shared.lock().unwrap().actions.clear();
+    windows[1] = create_mock_window(102, 1, 0, 1, 500.0);
+    windows[2] = create_mock_window(101, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows = windows.clone();
+    ctx.handle_event(Event::WindowLayoutsChanged {
+        changes: vec![
+            (102, windows[1].layout.clone()),
+            (101, windows[2].layout.clone()),
+        ],
+    })
+    .unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    // Closing the right column makes room for the stacked window again.
+    shared.lock().unwrap().state.windows.retain(|w| w.id != 101);
+    ctx.handle_event(Event::WindowClosed { id: 101 }).unwrap();
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::ConsumeOrExpelWindowRight { id: Some(102) }, ..]
+        ),
+        "unexpected actions: {:?}",
+        actions
+    );
+}
+
+#[test]
+fn test_max_columns_can_stack_into_last_column() {
+    let (mut ctx, shared) = setup_test(vec![create_mock_window(100, 1, 0, 0, 500.0)]);
+    ctx.config.max_columns = 1;
+    ctx.config.overflow_into = OverflowTarget::Last;
+    assert_eq!(ctx.config.max_columns_of(Some("other")), Some(1));
+    ctx.reconcile().unwrap();
+    shared.lock().unwrap().actions.clear();
+
+    let new = create_mock_window(101, 1, 0, 0, 500.0);
+    shared.lock().unwrap().state.windows =
+        vec![new.clone(), create_mock_window(100, 1, 1, 0, 968.0)];
+    shared.lock().unwrap().focused_window = Some(101);
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: new })
+        .unwrap();
+
+    let actions = &shared.lock().unwrap().actions;
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [
+                Action::MoveColumnToLast {},
+                Action::ConsumeOrExpelWindowLeft { id: Some(101) }
+            ]
+        ),
+        "unexpected actions: {:?}",
+        actions
+    );
+}
+
+#[test]
+fn test_accordion_follows_focus() {
+    let mut windows: Vec<Window> = (0..3)
+        .map(|col| create_mock_window(100 + col as u64, 1, col, 0, 300.0))
+        .collect();
+    windows[1].is_focused = true;
+    let (mut ctx, shared) = setup_test(windows.clone());
+    ctx.config.mode = Mode::Accordion;
+
+    ctx.reconcile().unwrap();
+    assert_eq!(
+        proportions_set(&shared.lock().unwrap().actions),
+        vec![(100, 25.0), (101, 75.0), (102, 25.0)]
+    );
+
+    shared.lock().unwrap().actions.clear();
+    windows[1].is_focused = false;
+    windows[2].is_focused = true;
+    shared.lock().unwrap().state.windows = windows;
+    ctx.handle_event(Event::WindowFocusChanged { id: Some(102) })
+        .unwrap();
+    let actions = &shared.lock().unwrap().actions;
+    assert_eq!(proportions_set(actions), vec![(101, 25.0), (102, 75.0)]);
+    assert!(
+        !actions
+            .iter()
+            .any(|a| matches!(a, Action::FocusWindow { .. }))
+    );
+}
+
+#[test]
+fn test_accordion_resizes_a_column_that_was_maximized_in_between() {
+    let mut a = create_mock_window(100, 1, 0, 0, 500.0);
+    a.is_focused = true;
+    let b = create_mock_window(101, 1, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![a.clone(), b]);
+    ctx.config.mode = Mode::Accordion;
+    ctx.reconcile().unwrap();
+    assert_eq!(
+        proportions_set(&shared.lock().unwrap().actions),
+        vec![(100, 75.0), (101, 25.0)]
+    );
+
+    // Closing B leaves A alone, so it gets maximized.
+    shared.lock().unwrap().actions.clear();
+    shared.lock().unwrap().state.windows = vec![a.clone()];
+    ctx.handle_event(Event::WindowClosed { id: 101 }).unwrap();
+    assert!(shared.lock().unwrap().actions.iter().any(is_maximize));
+
+    // C opens while A keeps focus; A has to shrink back even though we gave it 75% before.
+    shared.lock().unwrap().actions.clear();
+    let mut a = create_mock_window(100, 1, 0, 0, 968.0);
+    a.is_focused = true;
+    let c = create_mock_window(102, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows = vec![a, c.clone()];
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: c })
+        .unwrap();
+    assert_eq!(
+        proportions_set(&shared.lock().unwrap().actions),
+        vec![(100, 75.0), (102, 25.0)]
+    );
+}
+
+#[test]
+fn test_focus_changes_are_ignored_without_accordion() {
+    let (mut ctx, shared) = setup_test(vec![create_mock_window(100, 1, 0, 0, 968.0)]);
+    ctx.reconcile().unwrap();
+    let queries = shared.lock().unwrap().state_queries;
+
+    ctx.handle_event(Event::WindowFocusChanged { id: Some(100) })
+        .unwrap();
+    assert_eq!(shared.lock().unwrap().state_queries, queries);
+}
+
+#[test]
+fn test_monocle_maximizes_every_column_and_toggles() {
+    let left = create_mock_window(100, 1, 0, 0, 500.0);
+    let right = create_mock_window(101, 1, 1, 0, 500.0);
+    let (mut ctx, shared) = setup_test(vec![left, right]);
+    ctx.reconcile().unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    assert_eq!(ctx.toggle_monocle().unwrap(), Some((1, Mode::Monocle)));
+    let actions = shared.lock().unwrap().actions.clone();
+    assert_eq!(
+        actions.iter().filter(|a| is_maximize(a)).count(),
+        2,
+        "every column should be maximized: {:?}",
+        actions
+    );
+
+    shared.lock().unwrap().actions.clear();
+    shared.lock().unwrap().state.windows = vec![
+        create_mock_window(100, 1, 0, 0, 968.0),
+        create_mock_window(101, 1, 1, 0, 968.0),
+    ];
+    assert_eq!(ctx.toggle_monocle().unwrap(), Some((1, Mode::Maximize)));
+    let actions = &shared.lock().unwrap().actions;
+    assert_eq!(
+        actions
+            .iter()
+            .filter(|a| matches!(a, Action::SetWindowWidth { .. }) && !is_maximize(a))
+            .count(),
+        2,
+        "leaving monocle should restore both columns: {:?}",
+        actions
+    );
+}
+
+#[test]
+fn test_dwindle_alternates_columns_and_stacks() {
+    let windows = vec![
+        create_mock_window(100, 1, 0, 0, 500.0),
+        create_mock_window(101, 1, 1, 0, 500.0),
+        create_mock_window(102, 1, 2, 0, 500.0),
+    ];
+    let (mut ctx, shared) = setup_test(windows);
+    ctx.config.mode = Mode::Dwindle;
+
+    ctx.reconcile().unwrap();
+    let actions = shared.lock().unwrap().actions.clone();
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::ConsumeOrExpelWindowLeft { id: Some(102) }]
+        ),
+        "the third window should split the second column: {:?}",
+        actions
+    );
+
+    shared.lock().unwrap().actions.clear();
+    let stacked = create_mock_window(102, 1, 1, 1, 500.0);
+    shared.lock().unwrap().state.windows.retain(|w| w.id != 102);
+    shared.lock().unwrap().state.windows.push(stacked.clone());
+    ctx.handle_event(Event::WindowLayoutsChanged {
+        changes: vec![(102, stacked.layout.clone())],
+    })
+    .unwrap();
+    let actions = shared.lock().unwrap().actions.clone();
+    assert_eq!(proportions_set(&actions), vec![(100, 50.0), (101, 50.0)]);
+    assert!(
+        actions
+            .iter()
+            .any(|a| matches!(a, Action::ResetWindowHeight { id: Some(102) })),
+        "dwindle should split the column evenly: {:?}",
+        actions
+    );
+
+    // A fourth window opens a column of its own, halving the second one.
+    shared.lock().unwrap().actions.clear();
+    let fourth = create_mock_window(103, 1, 2, 0, 500.0);
+    shared.lock().unwrap().state.windows.push(fourth.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: fourth })
+        .unwrap();
+    assert_eq!(
+        proportions_set(&shared.lock().unwrap().actions),
+        vec![(101, 25.0), (103, 25.0)]
+    );
+}
+
+#[test]
+fn test_portrait_output_stacks_new_windows_into_one_column() {
+    let (mut ctx, shared) = setup_test(vec![create_mock_window(100, 1, 0, 0, 968.0)]);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .output_heights
+        .insert("eDP-1".to_string(), 1600.0);
+    ctx.config.stack_on_portrait = true;
+    ctx.reconcile().unwrap();
+    assert!(shared.lock().unwrap().actions.is_empty());
+
+    let opened = create_mock_window(101, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows.push(opened.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged {
+        window: opened.clone(),
+    })
+    .unwrap();
+    let actions = shared.lock().unwrap().actions.clone();
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::ConsumeOrExpelWindowLeft { id: Some(101) }]
+        ),
+        "unexpected actions: {:?}",
+        actions
+    );
+
+    // Landscape outputs keep opening columns.
+    let (mut ctx, shared) = setup_test(vec![create_mock_window(100, 1, 0, 0, 968.0)]);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .output_heights
+        .insert("eDP-1".to_string(), 600.0);
+    ctx.config.stack_on_portrait = true;
+    ctx.reconcile().unwrap();
+    shared.lock().unwrap().state.windows.push(opened.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: opened })
+        .unwrap();
+    assert!(
+        !shared
+            .lock()
+            .unwrap()
+            .actions
+            .iter()
+            .any(|a| matches!(a, Action::ConsumeOrExpelWindowLeft { .. }))
+    );
+
+    // Off unless configured.
+    let (mut ctx, shared) = setup_test(vec![create_mock_window(100, 1, 0, 0, 968.0)]);
+    shared
+        .lock()
+        .unwrap()
+        .state
+        .output_heights
+        .insert("eDP-1".to_string(), 1600.0);
+    ctx.reconcile().unwrap();
+    let opened = create_mock_window(101, 1, 1, 0, 500.0);
+    shared.lock().unwrap().state.windows.push(opened.clone());
+    ctx.handle_event(Event::WindowOpenedOrChanged { window: opened })
+        .unwrap();
+    assert!(
+        !shared
+            .lock()
+            .unwrap()
+            .actions
+            .iter()
+            .any(|a| matches!(a, Action::ConsumeOrExpelWindowLeft { .. }))
+    );
+}